@@ -0,0 +1,1151 @@
+use std::collections::HashMap;
+
+use crate::enums::*;
+
+/// Walks the AST before it reaches the engine, and reports type errors.
+///
+/// Anything the checker cannot figure out the type of is `MType::Undefined`, and is let through
+/// unchecked. The engine will still complain about it if it turns out to be nonsense.
+pub struct Checker {
+    scopes: Vec<HashMap<String, MType>>,
+    return_types: Vec<MType>,
+    generics: HashMap<String, Expr>, // Declarations of the generic functions, by name
+    params: Vec<HashMap<String, Vec<Expr>>>, // Parameters of the functions declared in each scope, for named and default arguments
+    instances: Vec<(String, Vec<MType>)>, // Every generic function with the types it is called with
+    errors: Vec<String>
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            return_types: vec![],
+            generics: HashMap::new(),
+            params: vec![HashMap::new()],
+            instances: vec![],
+            errors: vec![]
+        }
+    }
+
+    /// Checks the entire AST.
+    ///
+    /// This is the main entry point for the checker. Returns every error found, so an empty
+    /// vector means the program is fine.
+    pub fn check(&mut self, ast: &Vec<Expr>) -> Vec<String> {
+        for expr in ast {
+            self.check_statement(expr);
+        }
+
+        return self.errors.clone();
+    }
+
+    /// Rewrites every call with named or left out arguments in `ast` into one that passes every
+    /// argument by position, filling in default values. The engine only knows positions.
+    pub fn resolve_calls(&self, ast: &Vec<Expr>) -> Vec<Expr> {
+        let mut scopes = vec![HashMap::new()];
+        ast.iter().map(|expr| self.resolve_calls_in(expr, &mut scopes)).collect()
+    }
+
+    /// Rewrites the calls in `expr`. `scopes` has every name declared so far in each scope, with
+    /// the parameters of the function it is, so that a variable shadowing a function is not
+    /// called like one.
+    fn resolve_calls_in(&self, expr: &Expr, scopes: &mut Vec<HashMap<String, Option<Vec<Expr>>>>) -> Expr {
+        match expr {
+            Expr::If(cond, body, else_body) => Expr::If(
+                Box::new(self.resolve_calls_in(cond, scopes)),
+                self.resolve_calls_in_block(body, vec![], scopes),
+                self.resolve_calls_in_block(else_body, vec![], scopes)
+            ),
+            Expr::While(cond, body) => Expr::While(
+                Box::new(self.resolve_calls_in(cond, scopes)),
+                self.resolve_calls_in_block(body, vec![], scopes)
+            ),
+            Expr::For(names, iterable, body, line) => Expr::For(
+                names.clone(),
+                Box::new(self.resolve_calls_in(iterable, scopes)),
+                self.resolve_calls_in_block(body, names.clone(), scopes),
+                *line
+            ),
+            Expr::DecFunc(name, params, body, typ) => {
+                let params: Vec<Expr> = params.iter().map(|param| self.resolve_calls_in(param, scopes)).collect();
+                scopes.last_mut().unwrap().insert(name.clone(), Some(params.clone()));
+
+                let names = params.iter().filter_map(|param| match param {
+                    Expr::Parameter(name, _, _) | Expr::RestParameter(name, _) => Some(name.clone()),
+                    _ => None
+                }).collect();
+                let body = self.resolve_calls_in_block(body, names, scopes);
+                Expr::DecFunc(name.clone(), params, body, typ.clone())
+            }
+            Expr::BinOp(Operator::Declare(typ), pattern, value) => {
+                let value = self.resolve_calls_in(value, scopes);
+                let mut names = vec![];
+                declared_names(pattern, &mut names);
+                for name in names {
+                    scopes.last_mut().unwrap().insert(name, None);
+                }
+                Expr::BinOp(Operator::Declare(typ.clone()), pattern.clone(), Box::new(value))
+            }
+            Expr::CallFunc(callee, args) => {
+                let args: Vec<Expr> = args.iter().map(|arg| self.resolve_calls_in(arg, scopes)).collect();
+                let declared = match &**callee {
+                    Expr::Identifier(name) => scopes.iter().rev().find_map(|scope| scope.get(name)),
+                    _ => None
+                };
+                let args = match (&**callee, declared) {
+                    (Expr::Identifier(name), Some(Some(params))) => self.resolve_arguments(name, params, &args).unwrap_or(args),
+                    _ => args
+                };
+                Expr::CallFunc(Box::new(self.resolve_calls_in(callee, scopes)), Box::new(args))
+            }
+            _ => expr.map_children(&mut |child| self.resolve_calls_in(child, scopes))
+        }
+    }
+
+    /// Rewrites the calls in a block, which sees `names` as variables.
+    fn resolve_calls_in_block(&self, body: &Vec<Expr>, names: Vec<String>, scopes: &mut Vec<HashMap<String, Option<Vec<Expr>>>>) -> Box<Vec<Expr>> {
+        scopes.push(names.into_iter().map(|name| (name, None)).collect());
+        let body = body.iter().map(|expr| self.resolve_calls_in(expr, scopes)).collect();
+        scopes.pop();
+        Box::new(body)
+    }
+
+    /// The generic functions called by the program checked last, each with the types its type
+    /// parameters are bound to, in the order `Expr::type_params` lists them. The engine compiles
+    /// one copy of the function for each.
+    pub fn instances(&self) -> Vec<(String, Vec<MType>)> {
+        self.instances.clone()
+    }
+
+    fn check_block(&mut self, body: &Vec<Expr>) {
+        self.push_scope(HashMap::new());
+        for expr in body {
+            self.check_statement(expr);
+        }
+        self.pop_scope();
+    }
+
+    fn check_statement(&mut self, expr: &Expr) {
+        match expr {
+            Expr::If(cond, body, else_body) => {
+                self.infer(cond);
+                self.check_block(body);
+                self.check_block(else_body);
+            }
+            Expr::While(cond, body) => {
+                self.infer(cond);
+                self.check_block(body);
+            }
+            Expr::For(names, iterable, body, _) => {
+                let iterable_type = self.infer(iterable);
+                let element_types = match (&iterable_type, names.len()) {
+                    (MType::Map(key, value), _) => vec![*key.clone(), *value.clone()],
+                    (_, 2) => vec![MType::Number, element_type(&iterable_type)],
+                    _ => vec![element_type(&iterable_type)]
+                };
+
+                if names.len() > 2 {
+                    self.error(format!("A for loop takes one or two variables, got {}", names.len()));
+                }
+
+                let mut scope = HashMap::new();
+                for (name, typ) in names.iter().zip(element_types) {
+                    scope.insert(name.clone(), typ);
+                }
+
+                self.push_scope(scope);
+                for expr in body.iter() {
+                    self.check_statement(expr);
+                }
+                self.pop_scope();
+            }
+            Expr::Return(value) => {
+                let value_type = self.infer(value);
+                let expected = match self.return_types.last() {
+                    Some(t) => t.clone(),
+                    None => MType::Undefined
+                };
+                self.check_conversion(value, &value_type, &expected, "return value");
+            }
+            _ => {
+                self.infer(expr);
+            }
+        }
+    }
+
+    /// Infers the type of an expression, checking any sub-expressions along the way.
+    fn infer(&mut self, expr: &Expr) -> MType {
+        match expr {
+            Expr::Number(_) => MType::Number,
+            Expr::String(_) => MType::String,
+            Expr::Char(_) => MType::Char,
+
+            Expr::Interpolation(parts) => {
+                for part in parts.iter() {
+                    let typ = self.infer(part);
+                    if typ != MType::Undefined && typ != MType::String && !typ.is_numeric() {
+                        self.error(format!("Cannot interpolate {:?} into a string", typ));
+                    }
+                    // A field of a value of unknown type, like `{this.name}`, would be formatted as
+                    // a number whatever it holds. Calls into the core libraries are fine.
+                    if let (MType::Undefined, Expr::PropertyAccess(object, property)) = (&typ, part) {
+                        let known_object = match &**object {
+                            Expr::Identifier(name) => self.is_declared(name),
+                            _ => true
+                        };
+                        if known_object {
+                            self.error(format!(
+                                "Cannot interpolate `{}.{}`, the type of the field is not known",
+                                object.describe(), property.describe()
+                            ));
+                        }
+                    }
+                }
+
+                MType::String
+            }
+            Expr::Identifier(name) => self.lookup(name),
+
+            Expr::Cast(value, typ) => {
+                let from = self.infer(value);
+                if !typ.is_numeric() && *typ != MType::Char {
+                    self.error(format!("Cannot cast to {:?}, only numeric casts are supported", typ));
+                } else if from != MType::Undefined && !from.is_numeric() && from != MType::Char {
+                    self.error(format!("Cannot cast {:?} to {:?}", from, typ));
+                }
+
+                typ.clone()
+            }
+
+            Expr::BinOp(Operator::Declare(typ), left, right) => {
+                let name = match &**left {
+                    Expr::Identifier(name) => name.clone(),
+                    Expr::Tuple(_) | Expr::Array(_) | Expr::Map(_) => {
+                        self.check_destructure(left, typ, right);
+                        return MType::Null;
+                    }
+                    _ => return MType::Undefined
+                };
+
+                self.check_collection_type(typ);
+
+                let declared = match (typ, &**right) {
+                    (MType::Nested(_, element), Expr::Array(items)) => {
+                        self.check_array_literal(items, element, &format!("`{}`", name));
+                        typ.clone()
+                    }
+                    (MType::Map(key, value), Expr::Map(entries)) => {
+                        for (entry_key, entry_value) in entries.iter() {
+                            let key_type = self.infer(entry_key);
+                            self.check_conversion(entry_key, &key_type, key, &format!("key of `{}`", name));
+                            let value_type = self.infer(entry_value);
+                            self.check_conversion(entry_value, &value_type, value, &format!("value of `{}`", name));
+                        }
+                        typ.clone()
+                    }
+                    (MType::Undefined, _) => self.infer(right),
+                    _ => {
+                        let value_type = self.infer(right);
+                        self.check_conversion(right, &value_type, typ, &format!("`{}`", name));
+                        typ.clone()
+                    }
+                };
+
+                self.declare(&name, declared);
+                MType::Null
+            }
+
+            Expr::BinOp(Operator::Assign, left, right) => {
+                let value_type = self.infer(right);
+                match &**left {
+                    Expr::Identifier(name) => {
+                        let target = self.lookup(name);
+                        self.check_conversion(right, &value_type, &target, &format!("`{}`", name));
+                    }
+                    Expr::ArrayAccess(target, index, _) => {
+                        self.infer(index);
+                        match self.infer(target) {
+                            MType::String => self.error(format!("Cannot assign to characters of String `{}`", target.describe())),
+                            MType::Map(key, value) => {
+                                let key_type = self.infer(index);
+                                self.check_conversion(index, &key_type, &key, &format!("key of `{}`", target.describe()));
+                                self.check_conversion(right, &value_type, &value, &format!("value of `{}`", target.describe()));
+                            }
+                            typ => {
+                                let element = element_type(&typ);
+                                self.check_conversion(right, &value_type, &element, &format!("element of `{}`", target.describe()));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+
+                MType::Null
+            }
+
+            Expr::BinOp(op, left, right) => {
+                let left_type = self.infer(left);
+                let right_type = self.infer(right);
+
+                if left_type == MType::String || right_type == MType::String {
+                    return self.string_op_result(op, &left_type, &right_type);
+                }
+
+                if left_type == MType::Char || right_type == MType::Char {
+                    return self.char_op_result(op, &left_type, &right_type);
+                }
+
+                match op {
+                    Operator::Add | Operator::Subtract | Operator::Multiply | Operator::Divide => {
+                        self.arithmetic_result(left, &left_type, right, &right_type)
+                    }
+                    _ => {
+                        self.arithmetic_result(left, &left_type, right, &right_type);
+                        MType::Number
+                    }
+                }
+            }
+
+            Expr::DecFunc(name, params, body, typ) => {
+                let mut param_types = vec![];
+                let mut scope = HashMap::new();
+                for param in params {
+                    match param {
+                        Expr::Parameter(param_name, param_type, default) => {
+                            // Default values are evaluated where the function is called, so they
+                            // can not see the other parameters.
+                            if let Some(default) = default {
+                                let default_type = self.infer(default);
+                                self.check_conversion(default, &default_type, param_type, &format!("default value of `{}`", param_name));
+                            }
+
+                            // Arrow functions only get the types of their parameters from the
+                            // function they are passed to, when the parser already knows it.
+                            if *param_type == MType::Undefined && name.starts_with("anonymous_") {
+                                self.error(format!(
+                                    "The type of the parameter `{}` could not be inferred, write it like `({}: Number) => ...`",
+                                    param_name, param_name
+                                ));
+                            }
+
+                            self.check_collection_type(param_type);
+                            param_types.push(param_type.clone());
+                            scope.insert(param_name.clone(), param_type.clone());
+                        }
+                        Expr::RestParameter(param_name, param_type) => {
+                            if !matches!(param_type, MType::Array | MType::Nested(_, _)) {
+                                self.error(format!("The rest parameter `{}` must be an Array, got {:?}", param_name, param_type));
+                            }
+
+                            self.check_collection_type(param_type);
+                            param_types.push(param_type.clone());
+                            scope.insert(param_name.clone(), param_type.clone());
+                        }
+                        _ => {}
+                    }
+                }
+
+                let function_type = MType::Fn(param_types, Box::new(typ.clone()));
+                self.declare(name, function_type.clone());
+                self.params.last_mut().unwrap().insert(name.clone(), params.clone());
+                if function_type.is_generic() {
+                    self.generics.insert(name.clone(), expr.clone());
+                }
+
+                self.push_scope(scope);
+                self.return_types.push(typ.clone());
+                for expr in body.iter() {
+                    self.check_statement(expr);
+                }
+                self.return_types.pop();
+                self.pop_scope();
+
+                function_type
+            }
+
+            Expr::Closure(func, captures) => {
+                for name in captures.iter() {
+                    match self.lookup(name) {
+                        MType::Function | MType::Fn(_, _) | MType::Closure(_) => {
+                            self.error(format!("Closures can not capture the function `{}` yet", name));
+                        }
+                        MType::Nested(parent, _) if *parent == MType::Function => {
+                            self.error(format!("Closures can not capture the function `{}` yet", name));
+                        }
+                        _ => {}
+                    }
+                }
+
+                if let Expr::DecFunc(_, _, body, _) = &**func {
+                    self.check_captured_assignments(body, captures);
+                }
+
+                let function_type = self.infer(func);
+                MType::Closure(Box::new(function_type))
+            }
+
+            Expr::CallFunc(callee, args) => {
+                let mut variadic = false;
+                let params = match &**callee {
+                    Expr::Identifier(name) => self.lookup_params(name).cloned(),
+                    _ => None
+                };
+                let args = match (&**callee, params) {
+                    (Expr::Identifier(name), Some(params)) if matches!(self.lookup(name), MType::Fn(_, _)) => {
+                        variadic = matches!(params.last(), Some(Expr::RestParameter(_, _)));
+                        match self.resolve_arguments(name, &params, args) {
+                            Ok(args) => args,
+                            Err(message) => {
+                                self.error(message);
+                                args.iter().for_each(|arg| { self.infer(arg); });
+                                return MType::Undefined;
+                            }
+                        }
+                    }
+                    _ => {
+                        if args.iter().any(|arg| matches!(arg, Expr::NamedArg(_, _))) {
+                            self.error(format!("Named arguments can only be passed to functions declared with `fn`, not `{}`", callee.describe()));
+                        }
+                        if args.iter().any(|arg| matches!(arg, Expr::Spread(_))) {
+                            self.error(format!("Arrays can only be spread into functions declared with `fn`, not `{}`", callee.describe()));
+                        }
+                        args.to_vec()
+                    }
+                };
+                let arg_types: Vec<MType> = args.iter().map(|arg| self.infer(arg)).collect();
+
+                let name = match &**callee {
+                    Expr::Identifier(name) => name.clone(),
+                    _ => return MType::Undefined
+                };
+
+                let (mut param_types, return_type) = match self.lookup(&name) {
+                    MType::Fn(params, returns) => (params, *returns),
+                    MType::Closure(function_type) => match *function_type {
+                        MType::Fn(params, returns) => (params, *returns),
+                        _ => return MType::Undefined
+                    },
+                    MType::Nested(parent, returns) if *parent == MType::Function => return *returns,
+                    _ => return MType::Undefined
+                };
+
+                // Every argument after the fixed ones is an element of the rest parameter, unless
+                // a whole array is spread into it.
+                if variadic {
+                    let rest_type = param_types.pop().unwrap();
+                    match &args[param_types.len()..] {
+                        [Expr::Spread(_)] => param_types.push(rest_type),
+                        rest => param_types.extend(vec![element_type(&rest_type); rest.len()])
+                    }
+                }
+
+                if args.len() != param_types.len() {
+                    self.error(format!("`{}` takes {} arguments, got {}", name, param_types.len(), args.len()));
+                }
+
+                let (param_types, return_type) = match self.generics.get(&name).cloned() {
+                    Some(template) if self.lookup(&name).is_generic() => {
+                        match self.instantiate(&name, &template, &param_types, &arg_types) {
+                            Some(bindings) => (
+                                param_types.iter().map(|t| t.substitute(&bindings)).collect(),
+                                return_type.substitute(&bindings)
+                            ),
+                            None => return MType::Undefined
+                        }
+                    }
+                    _ => (param_types, return_type)
+                };
+
+                let mut i = 0;
+                while i < args.len() && i < param_types.len() {
+                    let context = format!("argument {} of `{}`", i + 1, name);
+                    self.check_conversion(&args[i], &arg_types[i], &param_types[i], &context);
+                    i += 1;
+                }
+
+                return_type
+            }
+
+            Expr::NamedArg(_, value) | Expr::Spread(value) => self.infer(value),
+
+            Expr::Array(items) => {
+                let types: Vec<MType> = items.iter().map(|item| self.infer(item)).collect();
+                let element = match types.first() {
+                    Some(t) => t.clone(),
+                    None => MType::Undefined
+                };
+
+                let mut i = 1;
+                while i < items.len() {
+                    self.check_conversion(&items[i], &types[i], &element, &format!("element {} of array", i + 1));
+                    i += 1;
+                }
+
+                MType::Nested(Box::new(MType::Array), Box::new(element))
+            }
+
+            Expr::Tuple(items) => {
+                MType::Tuple(items.iter().map(|item| self.infer(item)).collect())
+            }
+
+            Expr::Map(entries) => {
+                let mut key = MType::Undefined;
+                let mut value = MType::Undefined;
+                for (i, (entry_key, entry_value)) in entries.iter().enumerate() {
+                    let key_type = self.infer(entry_key);
+                    let value_type = self.infer(entry_value);
+                    if i == 0 {
+                        key = key_type;
+                        value = value_type;
+                    } else {
+                        self.check_conversion(entry_key, &key_type, &key, &format!("key {} of map", i + 1));
+                        self.check_conversion(entry_value, &value_type, &value, &format!("value {} of map", i + 1));
+                    }
+                }
+
+                let typ = MType::Map(Box::new(key), Box::new(value));
+                self.check_collection_type(&typ);
+                typ
+            }
+
+            Expr::ArrayAccess(target, index, _) => {
+                let target_type = self.infer(target);
+                let index_type = self.infer(index);
+                match &target_type {
+                    MType::Map(key, value) => {
+                        self.check_conversion(index, &index_type, key, &format!("key of `{}`", target.describe()));
+                        return *value.clone();
+                    }
+                    _ => {}
+                }
+
+                if index_type != MType::Undefined && !index_type.is_numeric() {
+                    self.error(format!("Cannot index `{}` with {:?}", target.describe(), index_type));
+                }
+
+                match target_type {
+                    MType::String => MType::Char,
+                    MType::Nested(_, element) => *element,
+                    _ => MType::Number
+                }
+            }
+
+            Expr::PropertyAccess(object, property) => {
+                let object_type = self.infer(object);
+                self.infer_property(&object_type, property)
+            }
+
+            _ => MType::Undefined
+        }
+    }
+
+    /// Puts the arguments of a call to the function `name` in the order of its parameters, taking
+    /// named arguments to the parameter with that name and filling in default values for the
+    /// ones left out.
+    fn resolve_arguments(&self, name: &String, params: &Vec<Expr>, args: &Vec<Expr>) -> Result<Vec<Expr>, String> {
+        // Whatever does not fit in the fixed parameters goes to the rest parameter, as is.
+        let fixed: Vec<&Expr> = params.iter().filter(|param| matches!(param, Expr::Parameter(_, _, _))).collect();
+        let rest_name = match params.last() {
+            Some(Expr::RestParameter(rest_name, _)) => Some(rest_name),
+            _ => None
+        };
+
+        let mut slots: Vec<Option<Expr>> = vec![None; fixed.len()];
+        let mut rest = vec![];
+        let mut named = false;
+        for (i, arg) in args.iter().enumerate() {
+            match arg {
+                Expr::NamedArg(arg_name, value) => {
+                    named = true;
+                    let position = fixed.iter().position(|param| match param {
+                        Expr::Parameter(param_name, _, _) => param_name == arg_name,
+                        _ => false
+                    });
+                    match position {
+                        Some(position) if slots[position].is_some() => {
+                            return Err(format!("Argument `{}` of `{}` is given twice", arg_name, name));
+                        }
+                        Some(position) => slots[position] = Some(*value.clone()),
+                        None => return Err(format!("`{}` has no parameter `{}`", name, arg_name))
+                    }
+                }
+                _ if named => {
+                    return Err(format!("Positional arguments to `{}` must come before named ones", name));
+                }
+                _ if i < fixed.len() => {
+                    if let (Expr::Spread(_), Expr::Parameter(param_name, _, _)) = (arg, fixed[i]) {
+                        return Err(format!("Cannot spread an array into `{}` of `{}`, only into a rest parameter", param_name, name));
+                    }
+                    slots[i] = Some(arg.clone());
+                }
+                _ if rest_name.is_some() => rest.push(arg.clone()),
+                _ => {
+                    return Err(format!("`{}` takes {} arguments, got {}", name, params.len(), args.len()));
+                }
+            }
+        }
+
+        if rest.len() > 1 && rest.iter().any(|arg| matches!(arg, Expr::Spread(_))) {
+            return Err(format!("A spread array must be the only argument for `...{}` of `{}`", rest_name.unwrap(), name));
+        }
+
+        let mut resolved = vec![];
+        for (slot, param) in slots.into_iter().zip(fixed.into_iter()) {
+            match (slot, param) {
+                (Some(arg), _) => resolved.push(arg),
+                (None, Expr::Parameter(_, _, Some(default))) => resolved.push(*default.clone()),
+                (None, Expr::Parameter(param_name, _, None)) => {
+                    return Err(format!("Missing argument `{}` of `{}`", param_name, name));
+                }
+                _ => {}
+            }
+        }
+        resolved.extend(rest);
+
+        Ok(resolved)
+    }
+
+    /// Works out the types the type parameters of the generic function `name` stand for in a call
+    /// with arguments of `arg_types`. Every new combination of types is recorded as an instance,
+    /// and the function is checked again with them.
+    fn instantiate(&mut self, name: &String, template: &Expr, param_types: &Vec<MType>, arg_types: &Vec<MType>) -> Option<HashMap<String, MType>> {
+        let mut bindings = HashMap::new();
+        for (param, arg) in param_types.iter().zip(arg_types.iter()) {
+            self.bind_type_params(name, param, arg, &mut bindings);
+        }
+
+        let type_params = template.type_params();
+        let mut types = vec![];
+        for type_param in type_params.iter() {
+            match bindings.get(type_param) {
+                Some(t) => types.push(t.clone()),
+                None => {
+                    self.error(format!("Cannot infer type `{}` for `{}`", type_param, name));
+                    return None;
+                }
+            }
+        }
+
+        // Calls inside another generic function are instantiated when that function is.
+        let concrete = types.iter().all(|t| !t.is_generic());
+        let instance = (name.clone(), types);
+        if concrete && !self.instances.contains(&instance) {
+            self.instances.push(instance);
+
+            self.push_scope(HashMap::new());
+            self.infer(&template.instantiate(name, &bindings));
+            self.pop_scope();
+        }
+
+        Some(bindings)
+    }
+
+    /// Binds the type parameters in `param` to the parts of `arg` in the same place, so that
+    /// `Array<T>` and `Array<String>` bind `T` to `String`.
+    fn bind_type_params(&mut self, name: &String, param: &MType, arg: &MType, bindings: &mut HashMap<String, MType>) {
+        match (param, arg) {
+            (_, MType::Undefined) => {}
+            (MType::Generic(type_param), _) => {
+                match bindings.get(type_param) {
+                    Some(bound) if bound != arg => {
+                        self.error(format!("Type `{}` of `{}` can not be both {:?} and {:?}", type_param, name, bound, arg));
+                    }
+                    Some(_) => {}
+                    None => {
+                        bindings.insert(type_param.clone(), arg.clone());
+                    }
+                }
+            }
+            (MType::Nested(param_parent, param_child), MType::Nested(arg_parent, arg_child))
+            | (MType::Map(param_parent, param_child), MType::Map(arg_parent, arg_child)) => {
+                self.bind_type_params(name, param_parent, arg_parent, bindings);
+                self.bind_type_params(name, param_child, arg_child, bindings);
+            }
+            (MType::Tuple(param_types), MType::Tuple(arg_types)) => {
+                for (param, arg) in param_types.iter().zip(arg_types.iter()) {
+                    self.bind_type_params(name, param, arg, bindings);
+                }
+            }
+            (MType::Fn(param_params, param_returns), MType::Fn(arg_params, arg_returns)) => {
+                for (param, arg) in param_params.iter().zip(arg_params.iter()) {
+                    self.bind_type_params(name, param, arg, bindings);
+                }
+                self.bind_type_params(name, param_returns, arg_returns, bindings);
+            }
+            (MType::Fn(_, _), MType::Closure(function_type)) => {
+                self.bind_type_params(name, param, function_type, bindings);
+            }
+            _ => {}
+        }
+    }
+
+    /// Infers the type of `property` on a value of `object_type`.
+    fn infer_property(&mut self, object_type: &MType, property: &Expr) -> MType {
+        match (object_type, property) {
+            (MType::String, Expr::Identifier(name)) => {
+                match name.as_str() {
+                    "length" => MType::Number,
+                    "text" => MType::Undefined,
+                    _ => {
+                        self.error(format!("String has no property `{}`", name));
+                        MType::Undefined
+                    }
+                }
+            }
+            (MType::Array | MType::Nested(_, _), _) => self.infer_array_property(object_type, property),
+            (MType::Map(key, _), _) => self.infer_map_property(key, property),
+            (MType::Tuple(types), Expr::Number(n)) => {
+                match types.get(*n as usize) {
+                    Some(t) if *n >= 0 => t.clone(),
+                    _ => {
+                        self.error(format!("Tuple of {} values has no value {}", types.len(), n));
+                        MType::Undefined
+                    }
+                }
+            }
+            _ => MType::Undefined
+        }
+    }
+
+    /// Checks `let (a, b) = value`, `let [a, b] = value` or `let { a, b } = value`, and declares
+    /// every name with the type of the value it takes.
+    fn check_destructure(&mut self, pattern: &Expr, typ: &MType, value: &Expr) {
+        let value_type = self.infer(value);
+        let typ = match typ {
+            MType::Undefined => value_type.clone(),
+            _ => {
+                self.check_conversion(value, &value_type, typ, "destructuring");
+                typ.clone()
+            }
+        };
+
+        let names: Vec<Expr> = match pattern {
+            Expr::Tuple(names) => names.to_vec(),
+            Expr::Array(names) => names.iter().map(|name| match name {
+                Expr::ArrayAccess(name, _, _) => *name.clone(),
+                _ => name.clone()
+            }).collect(),
+            Expr::Map(fields) => fields.iter().map(|(_, name)| name.clone()).collect(),
+            _ => vec![]
+        };
+
+        let types = match (pattern, &typ) {
+            (_, MType::Undefined) => vec![],
+            (Expr::Tuple(_), MType::Tuple(types)) => {
+                if types.len() != names.len() {
+                    self.error(format!("Cannot destructure a tuple of {} values into {} names", types.len(), names.len()));
+                }
+                types.clone()
+            }
+            // How many elements there are is only known at runtime, where the indices are checked.
+            (Expr::Array(_), MType::String) => vec![MType::Char; names.len()],
+            (Expr::Array(_), MType::Array | MType::Nested(_, _)) => vec![element_type(&typ); names.len()],
+            (Expr::Map(fields), MType::String | MType::Array | MType::Nested(_, _) | MType::Map(_, _)) => {
+                fields.iter().map(|(field, _)| self.infer_property(&typ, field)).collect()
+            }
+            (Expr::Tuple(_), _) => {
+                self.error(format!("Cannot destructure {:?} into values, expected a tuple", typ));
+                vec![]
+            }
+            (Expr::Array(_), _) => {
+                self.error(format!("Cannot destructure {:?} into elements, expected an array or string", typ));
+                vec![]
+            }
+            (_, MType::Class | MType::Struct) => {
+                self.error(format!("Cannot destructure {:?} into fields, classes and structs are not supported yet", typ));
+                vec![]
+            }
+            _ => {
+                self.error(format!("Cannot destructure {:?} into fields, its fields are not known", typ));
+                vec![]
+            }
+        };
+
+        for (i, name) in names.iter().enumerate() {
+            if let Expr::Identifier(name) = name {
+                let typ = types.get(i).cloned().unwrap_or(MType::Undefined);
+                self.declare(name, typ);
+            }
+        }
+    }
+
+    /// Reports assignments to captured variables, which would only change the closure's copy.
+    fn check_captured_assignments(&mut self, body: &Vec<Expr>, captures: &Vec<String>) {
+        for expr in body.iter() {
+            match expr {
+                Expr::BinOp(Operator::Assign, left, _) => {
+                    if let Expr::Identifier(name) = &**left {
+                        if captures.contains(name) {
+                            self.error(format!("Cannot assign to `{}`, closures capture variables by value", name));
+                        }
+                    }
+                }
+                Expr::If(_, body, else_body) => {
+                    self.check_captured_assignments(body, captures);
+                    self.check_captured_assignments(else_body, captures);
+                }
+                Expr::While(_, body) | Expr::For(_, _, body, _) => {
+                    self.check_captured_assignments(body, captures);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Checks the items of an array literal against the element type it is declared with.
+    fn check_array_literal(&mut self, items: &Vec<Expr>, element: &MType, context: &str) {
+        for item in items.iter() {
+            match (element, item) {
+                (MType::Nested(_, inner), Expr::Array(inner_items)) => {
+                    self.check_array_literal(inner_items, inner, context);
+                }
+                _ => {
+                    let item_type = self.infer(item);
+                    self.check_conversion(item, &item_type, element, &format!("element of {}", context));
+                }
+            }
+        }
+    }
+
+    /// Reports array and map types with elements that can not be stored in them yet.
+    fn check_collection_type(&mut self, typ: &MType) {
+        match typ {
+            MType::Map(key, value) => {
+                if **key != MType::String && !key.is_numeric() && **key != MType::Undefined {
+                    self.error(format!("Map keys must be String or a number, got {:?}", key));
+                }
+                match **value {
+                    MType::Struct | MType::Class | MType::Function | MType::Fn(_, _) => {
+                        self.error(format!("Maps of {:?} are not supported, only numbers, strings and arrays", value));
+                    }
+                    _ => self.check_collection_type(value)
+                }
+            }
+            MType::Nested(parent, element) if **parent == MType::Array => {
+                match **element {
+                    MType::Struct | MType::Class => {
+                        self.error(format!("Arrays of {:?}, like `Array<Person>`, are not supported yet, classes and structs are not compiled", element));
+                    }
+                    MType::Function | MType::Fn(_, _) => {
+                        self.error(format!("Arrays of {:?} are not supported, only numbers, strings and arrays", element));
+                    }
+                    _ => self.check_collection_type(element)
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Infers the type of `length`, `has(key)` or `remove(key)` on a map.
+    fn infer_map_property(&mut self, key: &MType, property: &Expr) -> MType {
+        match property {
+            Expr::Identifier(name) if name == "length" => MType::Number,
+            Expr::CallFunc(callee, args) => {
+                let name = match &**callee {
+                    Expr::Identifier(name) => name.clone(),
+                    _ => return MType::Undefined
+                };
+
+                let arg_types: Vec<MType> = args.iter().map(|arg| self.infer(arg)).collect();
+                match (name.as_str(), args.len()) {
+                    ("has", 1) | ("remove", 1) => {
+                        self.check_conversion(&args[0], &arg_types[0], key, &format!("argument 1 of `{}`", name));
+                        MType::Number
+                    }
+                    _ => {
+                        self.error(format!("Map has no method `{}` taking {} arguments", name, args.len()));
+                        MType::Undefined
+                    }
+                }
+            }
+            _ => {
+                self.error(format!("Map has no property {:?}", property));
+                MType::Undefined
+            }
+        }
+    }
+
+    /// Infers the type of `length`, `push(value)` or `pop()` on an array.
+    fn infer_array_property(&mut self, array_type: &MType, property: &Expr) -> MType {
+        let element = element_type(array_type);
+        match property {
+            Expr::Identifier(name) if name == "length" => MType::Number,
+            Expr::CallFunc(callee, args) => {
+                let name = match &**callee {
+                    Expr::Identifier(name) => name.clone(),
+                    _ => return MType::Undefined
+                };
+
+                let arg_types: Vec<MType> = args.iter().map(|arg| self.infer(arg)).collect();
+                match (name.as_str(), args.len()) {
+                    ("push", 1) => {
+                        self.check_conversion(&args[0], &arg_types[0], &element, "argument 1 of `push`");
+                        MType::Number
+                    }
+                    ("pop", 0) => element,
+                    _ => {
+                        self.error(format!("Array has no method `{}` taking {} arguments", name, args.len()));
+                        MType::Undefined
+                    }
+                }
+            }
+            _ => {
+                self.error(format!("Array has no property {:?}", property));
+                MType::Undefined
+            }
+        }
+    }
+
+    /// Works out the type of `left <op> right` for arithmetic, reporting mixes of numeric types
+    /// that would need a lossy conversion.
+    fn arithmetic_result(&mut self, left: &Expr, left_type: &MType, right: &Expr, right_type: &MType) -> MType {
+        if !left_type.is_numeric() || !right_type.is_numeric() {
+            return left_type.clone();
+        }
+
+        // Literals adapt to whatever they are combined with.
+        match (left, right) {
+            (Expr::Number(_), Expr::Number(_)) => return MType::Number,
+            (Expr::Number(n), _) => {
+                self.check_literal(*n, right_type);
+                return right_type.clone();
+            }
+            (_, Expr::Number(n)) => {
+                self.check_literal(*n, left_type);
+                return left_type.clone();
+            }
+            _ => {}
+        }
+
+        if left_type.widens_to(right_type) {
+            return right_type.clone();
+        }
+        if right_type.widens_to(left_type) {
+            return left_type.clone();
+        }
+
+        self.error(format!(
+            "Cannot mix {:?} and {:?} in one expression without a cast (use `as`)",
+            left_type, right_type
+        ));
+
+        return left_type.clone();
+    }
+
+    /// Works out the type of an operator where at least one side is a string.
+    fn string_op_result(&mut self, op: &Operator, left_type: &MType, right_type: &MType) -> MType {
+        let other = match left_type {
+            MType::String => right_type,
+            _ => left_type
+        };
+
+        match op {
+            Operator::Add => {
+                if other.is_numeric() {
+                    self.error(format!("Cannot add {:?} to String, convert it with `string.ntos` first", other));
+                }
+                MType::String
+            }
+            Operator::Subtract | Operator::Multiply | Operator::Divide => {
+                self.error(format!("Operator {:?} is not supported on strings", op));
+                MType::String
+            }
+            _ => {
+                if other.is_numeric() {
+                    self.error(format!("Cannot compare String with {:?}", other));
+                }
+                MType::Number
+            }
+        }
+    }
+
+    /// Works out the type of an operator where at least one side is a `Char`.
+    ///
+    /// Characters can be compared with each other, anything else needs a cast.
+    fn char_op_result(&mut self, op: &Operator, left_type: &MType, right_type: &MType) -> MType {
+        match op {
+            Operator::Add | Operator::Subtract | Operator::Multiply | Operator::Divide => {
+                self.error(format!("Cannot do arithmetic on Char, cast it to a number with `as` first"));
+                MType::Char
+            }
+            _ => {
+                if left_type.is_numeric() || right_type.is_numeric() {
+                    self.error(format!("Cannot compare {:?} with {:?}, cast with `as` first", left_type, right_type));
+                }
+                MType::Number
+            }
+        }
+    }
+
+    /// Reports an error if a value of type `from` can not be implicitly used as `to`.
+    fn check_conversion(&mut self, value: &Expr, from: &MType, to: &MType, context: &str) {
+        if *from == MType::Undefined || *to == MType::Undefined {
+            return;
+        }
+
+        if from.is_numeric() && to.is_numeric() {
+            match value {
+                Expr::Number(n) => {
+                    self.check_literal(*n, to);
+                }
+                _ => {
+                    if !from.widens_to(to) {
+                        self.error(format!(
+                            "Cannot implicitly convert {:?} to {:?} for {}, use `as {:?}` to convert explicitly",
+                            from, to, context, to
+                        ));
+                    }
+                }
+            }
+            return;
+        }
+
+        if from.is_numeric() != to.is_numeric() {
+            self.error(format!("Expected {:?} for {}, got {:?}", to, context, from));
+            return;
+        }
+
+        match (from, to) {
+            (MType::Nested(_, from_element), MType::Nested(_, to_element)) if from_element != to_element => {
+                // Elements are not converted, so they have to match exactly.
+                if **from_element != MType::Undefined && **to_element != MType::Undefined {
+                    self.error(format!("Expected {:?} for {}, got {:?}", to, context, from));
+                }
+            }
+            (MType::Map(from_key, from_value), MType::Map(to_key, to_value)) => {
+                let matches = |from: &MType, to: &MType| from == to || *from == MType::Undefined || *to == MType::Undefined;
+                if !matches(from_key, to_key) || !matches(from_value, to_value) {
+                    self.error(format!("Expected {:?} for {}, got {:?}", to, context, from));
+                }
+            }
+            (MType::String, MType::Nested(_, _) | MType::Map(_, _)) | (MType::Nested(_, _) | MType::Map(_, _), MType::String) => {
+                self.error(format!("Expected {:?} for {}, got {:?}", to, context, from));
+            }
+            (MType::Map(_, _), MType::Nested(_, _)) | (MType::Nested(_, _), MType::Map(_, _)) => {
+                self.error(format!("Expected {:?} for {}, got {:?}", to, context, from));
+            }
+            (MType::Closure(function_type), _) => {
+                self.check_conversion(value, function_type, to, context);
+            }
+            (MType::Fn(from_params, from_returns), MType::Fn(to_params, to_returns)) => {
+                // Functions are not converted either, so the parameters and return type have to
+                // match exactly, except where they are unknown.
+                let matches = |from: &MType, to: &MType| from == to || *from == MType::Undefined || *to == MType::Undefined;
+                let params_match = from_params.len() == to_params.len()
+                    && from_params.iter().zip(to_params.iter()).all(|(from, to)| matches(from, to));
+                if !params_match || !matches(from_returns, to_returns) {
+                    self.error(format!("Expected {:?} for {}, got {:?}", to, context, from));
+                }
+            }
+            (MType::Fn(_, from_returns), MType::Nested(parent, to_returns)) if **parent == MType::Function => {
+                if **from_returns != MType::Undefined && **to_returns != MType::Undefined && from_returns != to_returns {
+                    self.error(format!("Expected {:?} for {}, got {:?}", to, context, from));
+                }
+            }
+            (MType::Tuple(from_types), MType::Tuple(to_types)) => {
+                if from_types.len() != to_types.len() {
+                    self.error(format!("Expected {:?} for {}, got {:?}", to, context, from));
+                    return;
+                }
+
+                // Check the values of a literal one by one, so that number literals adapt.
+                let mut i = 0;
+                while i < to_types.len() {
+                    let item = match value {
+                        Expr::Tuple(items) => &items[i],
+                        _ => value
+                    };
+                    self.check_conversion(item, &from_types[i], &to_types[i], &format!("value {} of {}", i + 1, context));
+                    i += 1;
+                }
+            }
+            (MType::Tuple(_), _) | (_, MType::Tuple(_)) => {
+                self.error(format!("Expected {:?} for {}, got {:?}", to, context, from));
+            }
+            // Inside a generic function nothing is known about a type parameter, so only values of
+            // that same parameter can be used for it.
+            (MType::Generic(_), _) | (_, MType::Generic(_)) if from != to => {
+                self.error(format!("Expected {:?} for {}, got {:?}", to, context, from));
+            }
+            _ => {}
+        }
+    }
+
+    fn check_literal(&mut self, n: i64, typ: &MType) {
+        if !typ.fits_literal(n) {
+            self.error(format!("Literal {} does not fit in {:?}", n, typ));
+        }
+    }
+
+    fn declare(&mut self, name: &String, typ: MType) {
+        let scope = self.scopes.last_mut().unwrap();
+        scope.insert(name.clone(), typ);
+    }
+
+    fn push_scope(&mut self, scope: HashMap<String, MType>) {
+        self.scopes.push(scope);
+        self.params.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+        self.params.pop();
+    }
+
+    /// The parameters of the function `name` refers to, unless a variable declared in a scope
+    /// closer to this one hides it.
+    fn lookup_params(&self, name: &String) -> Option<&Vec<Expr>> {
+        for (scope, params) in self.scopes.iter().zip(self.params.iter()).rev() {
+            if scope.contains_key(name) {
+                return params.get(name);
+            }
+        }
+
+        None
+    }
+
+    /// Whether `name` is a variable or function declared in scope, whatever its type.
+    fn is_declared(&self, name: &String) -> bool {
+        self.scopes.iter().any(|scope| scope.contains_key(name))
+    }
+
+    fn lookup(&self, name: &String) -> MType {
+        for scope in self.scopes.iter().rev() {
+            match scope.get(name) {
+                Some(t) => return t.clone(),
+                None => {}
+            }
+        }
+
+        return MType::Undefined;
+    }
+
+    fn error(&mut self, message: String) {
+        // Instances of generic functions are checked again, which finds the same errors twice.
+        if self.errors.contains(&message) {
+            return;
+        }
+        self.errors.push(message);
+    }
+}
+
+/// The element type of an array type, or `Undefined` if it is not known.
+fn element_type(typ: &MType) -> MType {
+    match typ {
+        MType::Nested(_, element) => *element.clone(),
+        _ => MType::Undefined
+    }
+}
+
+/// The names a declaration of `pattern` declares, one for `let x` and several when destructuring.
+fn declared_names(pattern: &Expr, names: &mut Vec<String>) {
+    match pattern {
+        Expr::Identifier(name) => names.push(name.clone()),
+        _ => {
+            pattern.map_children(&mut |child| {
+                declared_names(child, names);
+                child.clone()
+            });
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+
+use colored::*;
+
+/// A flag of a command, like `--output <file>`.
+pub struct Flag {
+    pub long: &'static str,
+    pub short: Option<&'static str>,
+    pub value: Option<&'static str>, // The name of the value the flag takes, if it takes one
+    pub help: &'static str
+}
+
+/// A subcommand of `myst`, like `myst run`.
+pub struct Command {
+    pub name: &'static str,
+    pub arguments: &'static str, // What follows the flags in its usage, like `<source file>`
+    pub files: (usize, usize), // The least and most positional arguments it takes
+    pub forwards: bool, // Whether the arguments after `--` are passed on to the program
+    pub help: &'static str,
+    pub flags: &'static [Flag]
+}
+
+const DEBUG: Flag = Flag { long: "--debug", short: Some("-d"), value: None, help: "Run Rainbow in debug mode" };
+const UNCHECKED: Flag = Flag { long: "--unchecked", short: None, value: None, help: "Do not check array and string indices" };
+const RAINBOW: Flag = Flag { long: "--rainbow", short: None, value: Some("<path>"), help: "Path of the Rainbow VM" };
+const LIB: Flag = Flag { long: "--lib", short: Some("-l"), value: Some("<dir>"), help: "Directory of Rainbow libraries, can be repeated" };
+const OUTPUT: Flag = Flag { long: "--output", short: Some("-o"), value: Some("<file>"), help: "Where to write the bytecode" };
+const HELP: Flag = Flag { long: "--help", short: Some("-h"), value: None, help: "Display this help message" };
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "run",
+        arguments: "<source file> [-- <program arguments>]",
+        files: (1, 1),
+        forwards: true,
+        help: "Compile a program and run it with Rainbow",
+        flags: &[DEBUG, OUTPUT, UNCHECKED, RAINBOW, LIB, HELP]
+    },
+    Command {
+        name: "build",
+        arguments: "[source file | project directory]",
+        files: (0, 1),
+        forwards: false,
+        help: "Compile a program, or every module of the project in a directory, to bytecode",
+        flags: &[
+            Flag { long: "--debug", short: Some("-d"), value: None, help: "List every file built" },
+            OUTPUT, UNCHECKED, HELP
+        ]
+    },
+    Command {
+        name: "check",
+        arguments: "<source file>",
+        files: (1, 1),
+        forwards: false,
+        help: "Check a program for errors without compiling it",
+        flags: &[HELP]
+    },
+    Command {
+        name: "test",
+        arguments: "",
+        files: (0, 0),
+        forwards: false,
+        help: "Run the tests of Myst itself",
+        flags: &[DEBUG, RAINBOW, LIB, HELP]
+    },
+    Command {
+        name: "fmt",
+        arguments: "<source files>",
+        files: (1, usize::MAX),
+        forwards: false,
+        help: "Format source files in place",
+        flags: &[
+            Flag { long: "--check", short: None, value: None, help: "Only list the files that are not formatted" },
+            HELP
+        ]
+    },
+    Command {
+        name: "tokens",
+        arguments: "<source file>",
+        files: (1, 1),
+        forwards: false,
+        help: "Print the tokens of a source file",
+        flags: &[HELP]
+    },
+    Command {
+        name: "ast",
+        arguments: "<source file>",
+        files: (1, 1),
+        forwards: false,
+        help: "Print the syntax tree of a source file",
+        flags: &[HELP]
+    }
+];
+
+/// The command line, parsed.
+pub struct Options {
+    pub command: &'static Command,
+    pub files: Vec<String>,
+    pub arguments: Vec<String>, // What follows `--`, for the program to run
+    flags: HashMap<&'static str, Vec<String>> // The values given to each flag, by its long name
+}
+
+impl Options {
+    /// Whether the flag `long` was passed.
+    pub fn has(&self, long: &str) -> bool {
+        self.flags.contains_key(long)
+    }
+
+    /// The value of the flag `long`, the last one if it was passed several times.
+    pub fn value(&self, long: &str) -> Option<String> {
+        self.flags.get(long).and_then(|values| values.last().cloned())
+    }
+
+    /// Every value passed to the flag `long`.
+    pub fn values(&self, long: &str) -> Vec<String> {
+        self.flags.get(long).cloned().unwrap_or_default()
+    }
+}
+
+/// Parses the arguments `myst` was run with, without the name of the program itself.
+///
+/// A source file without a command, like `myst app.myst`, is run. Returns `None` when help
+/// was asked for, and printed.
+pub fn parse(args: Vec<String>) -> Result<Option<Options>, String> {
+    let mut args = args.into_iter().peekable();
+    let name = match args.peek().map(|arg| arg.as_str()) {
+        None | Some("--help") | Some("-h") | Some("help") => {
+            args.next();
+            match args.next() {
+                Some(name) => print_command_help(find(&name)?),
+                None => print_help()
+            }
+            return Ok(None);
+        }
+        Some(arg) if arg.ends_with(".myst") => "run".to_string(),
+        Some(_) => args.next().unwrap()
+    };
+    let command = find(&name)?;
+
+    let mut files = vec![];
+    let mut arguments = vec![];
+    let mut flags: HashMap<&'static str, Vec<String>> = HashMap::new();
+    while let Some(arg) = args.next() {
+        // After `--`, arguments are never flags of `myst`.
+        if arg == "--" {
+            match command.forwards {
+                true => arguments.extend(args.by_ref()),
+                false => files.extend(args.by_ref())
+            }
+            break;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            files.push(arg);
+            continue;
+        }
+
+        let flag = match command.flags.iter().find(|flag| flag.long == arg || flag.short == Some(arg.as_str())) {
+            Some(flag) => flag,
+            None => return Err(format!("Unknown flag `{}` for `myst {}`, see `myst {} --help`", arg, command.name, command.name))
+        };
+        if flag.long == "--help" {
+            print_command_help(command);
+            return Ok(None);
+        }
+
+        let value = match flag.value {
+            Some(value_name) => match args.next() {
+                Some(value) if !value.starts_with('-') || value == "-" => value,
+                _ => return Err(format!("`{}` needs a value, like `{} {}`", arg, arg, value_name))
+            },
+            None => String::new()
+        };
+        flags.entry(flag.long).or_default().push(value);
+    }
+
+    let (least, most) = command.files;
+    if files.len() < least {
+        return Err(format!("`myst {}` needs {}, see `myst {} --help`", command.name, command.arguments, command.name));
+    }
+    if files.len() > most {
+        return Err(format!("Unexpected argument `{}` for `myst {}`, see `myst {} --help`", files[most], command.name, command.name));
+    }
+
+    Ok(Some(Options {
+        command,
+        files,
+        arguments,
+        flags
+    }))
+}
+
+fn find(name: &String) -> Result<&'static Command, String> {
+    match COMMANDS.iter().find(|command| command.name == name) {
+        Some(command) => Ok(command),
+        None => Err(format!("Unknown command `{}`, see `myst --help`", name))
+    }
+}
+
+fn print_help() {
+    println!("\nUsage: {} {} {}\n", "myst".blue(), "<command>".cyan(), "[options]".cyan());
+    println!("Commands:");
+    for command in COMMANDS.iter() {
+        println!("  {:<8} {}", command.name.cyan(), command.help);
+    }
+    println!("\nExample:");
+    println!("  {} run -d -o {} {}", "myst".blue(), "build.rbb".green(), "source.myst".green());
+    println!("  {} {}", "myst".blue(), "source.myst".green());
+    println!("  {} run {} -- {}", "myst".blue(), "server.myst".green(), "--port 8080".cyan());
+    println!("\nSee `myst <command> --help` for the options of each command.\n");
+}
+
+fn print_command_help(command: &Command) {
+    println!("\n{}\n", command.help);
+    println!("Usage: {} {} {} {}\n", "myst".blue(), command.name.cyan(), "[options]".cyan(), command.arguments.green());
+    println!("Options:");
+    for flag in command.flags.iter() {
+        let names = match flag.short {
+            Some(short) => format!("{} {}", flag.long, short),
+            None => flag.long.to_string()
+        };
+        let value = flag.value.unwrap_or("");
+        let padding = " ".repeat(24usize.saturating_sub(names.len() + value.len() + 1));
+        println!("  {} {}{} {}", names.cyan(), value.green(), padding, flag.help);
+    }
+    println!();
+}
@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The name of the file configuring a Myst project, in its root directory.
+pub const MANIFEST: &str = "myst.toml";
+
+/// The settings of a project, read from its `myst.toml`.
+///
+/// Only the part of TOML Myst needs is supported: `[sections]`, and `key = value` where the value
+/// is a string, an array of strings, or a bare word like a number. Each setting is stored under
+/// `section.key`, as the list of its values.
+pub struct Manifest {
+    pub path: PathBuf,
+    settings: HashMap<String, Vec<String>>
+}
+
+impl Manifest {
+    /// Reads the manifest at `path`.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e))
+        };
+
+        let mut settings = HashMap::new();
+        let mut section = String::new();
+        let mut lines = source.lines().enumerate();
+        while let Some((i, line)) = lines.next() {
+            let error = |message: &str| format!("{}:{}: {}", path.display(), i + 1, message);
+            let mut line = strip_comment(line).trim().to_string();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                match line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                    Some(name) if !name.trim().is_empty() => section = name.trim().to_string(),
+                    _ => return Err(error(&format!("Invalid section `{}`", line)))
+                }
+                continue;
+            }
+
+            // Arrays may be spread over several lines.
+            if line.split_once('=').is_some_and(|(_, value)| value.trim_start().starts_with('[')) {
+                while !line.ends_with(']') {
+                    match lines.next() {
+                        Some((_, next)) => line = format!("{} {}", line, strip_comment(next).trim()),
+                        None => return Err(error("Unterminated array"))
+                    }
+                }
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) if !key.trim().is_empty() => (key.trim(), value.trim()),
+                _ => return Err(error(&format!("Expected `key = value`, got `{}`", line)))
+            };
+            let values = parse_value(value).map_err(|e| error(&e))?;
+
+            let key = match section.is_empty() {
+                true => key.to_string(),
+                false => format!("{}.{}", section, key)
+            };
+            settings.insert(key, values);
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            settings
+        })
+    }
+
+    /// Finds the manifest of the project `path` is in, looking in its directory and every
+    /// directory above it.
+    pub fn find(path: &Path) -> Option<PathBuf> {
+        let path = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+        let mut directory = match path.is_dir() {
+            true => Some(path.as_path()),
+            false => path.parent()
+        };
+        while let Some(current) = directory {
+            let manifest = current.join(MANIFEST);
+            if manifest.is_file() {
+                return Some(manifest);
+            }
+            directory = current.parent();
+        }
+
+        None
+    }
+
+    /// The single value of the setting `key`, such as `package.name`.
+    pub fn string(&self, key: &str) -> Result<Option<String>, String> {
+        match self.settings.get(key).map(|values| values.as_slice()) {
+            None => Ok(None),
+            Some([value]) => Ok(Some(value.clone())),
+            Some(_) => Err(format!("`{}` in {} should be a single value", key, self.path.display()))
+        }
+    }
+
+    /// The values of the setting `key`, which may be a single value too.
+    pub fn strings(&self, key: &str) -> Option<Vec<String>> {
+        self.settings.get(key).cloned()
+    }
+
+    /// The path in the setting `key`, relative to the directory of the manifest.
+    pub fn path(&self, key: &str) -> Result<Option<String>, String> {
+        Ok(self.string(key)?.map(|path| self.relative(&path)))
+    }
+
+    /// The paths in the setting `key`, relative to the directory of the manifest.
+    pub fn paths(&self, key: &str) -> Option<Vec<String>> {
+        self.strings(key).map(|paths| paths.iter().map(|path| self.relative(path)).collect())
+    }
+
+    fn relative(&self, path: &String) -> String {
+        match self.path.parent() {
+            Some(directory) => directory.join(path).display().to_string(),
+            None => path.clone()
+        }
+    }
+}
+
+/// A Myst project, from the `[package]` section of its `myst.toml`:
+///
+/// ```toml
+/// [package]
+/// name = "server"
+/// version = "0.1.0"
+/// entry = "src/main.myst"  # The program, built to `<output>/<name>.rbb`
+/// sources = ["src"]        # Directories with its modules, each built to its own `.rbb`
+/// libraries = ["vendor"]   # Directories its imports are looked up in, besides the sources
+/// output = "build"
+/// ```
+///
+/// `sources` defaults to the directory of the entry point, `libraries` to none, and `output`
+/// to `build`. Every path is relative to the `myst.toml`.
+pub struct Project {
+    pub name: String,
+    pub version: String,
+    pub entry: PathBuf,
+    pub sources: Vec<PathBuf>,
+    pub libraries: Vec<PathBuf>,
+    pub output: PathBuf
+}
+
+impl Project {
+    pub fn read(manifest: &Manifest) -> Result<Self, String> {
+        let required = |key: &str| match manifest.string(key) {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => Err(format!("{} is missing `{}` in `[package]`", manifest.path.display(), key.split_once('.').unwrap().1)),
+            Err(e) => Err(e)
+        };
+
+        let name = required("package.name")?;
+        let version = required("package.version")?;
+        let entry = PathBuf::from(manifest.path("package.entry")?.ok_or(
+            format!("{} is missing `entry` in `[package]`", manifest.path.display())
+        )?);
+        if !entry.is_file() {
+            return Err(format!("The entry point {} of {} does not exist", entry.display(), name));
+        }
+
+        let sources: Vec<PathBuf> = match manifest.paths("package.sources") {
+            Some(sources) => sources.iter().map(PathBuf::from).collect(),
+            None => vec![entry.parent().unwrap_or(Path::new(".")).to_path_buf()]
+        };
+        let libraries: Vec<PathBuf> = manifest.paths("package.libraries").unwrap_or_default().iter().map(PathBuf::from).collect();
+        for directory in sources.iter().chain(libraries.iter()) {
+            if !directory.is_dir() {
+                return Err(format!("The directory {} of {} does not exist", directory.display(), name));
+            }
+        }
+
+        let output = PathBuf::from(manifest.path("package.output")?.unwrap_or(manifest.relative(&"build".to_string())));
+
+        Ok(Self {
+            name,
+            version,
+            entry,
+            sources,
+            libraries,
+            output
+        })
+    }
+}
+
+/// Where the Rainbow VM running Myst programs is, and the directories with the libraries they use.
+pub struct Rainbow {
+    pub path: String,
+    pub libraries: Vec<String>
+}
+
+impl Rainbow {
+    /// Finds Rainbow, from the first of these that sets it:
+    ///  1. The `--rainbow <path>` and `--lib <dir>` flags, passed here.
+    ///  2. The `RAINBOW_PATH` and `RAINBOW_LIB` environment variables, several directories in
+    ///     `RAINBOW_LIB` are separated by `:`.
+    ///  3. `path` and `libraries` in the `[rainbow]` section of the project's `myst.toml`.
+    ///
+    /// Without any of them, the VM is looked up as `rainbow` in `PATH`. There is no default for
+    /// the libraries.
+    pub fn find(path: Option<String>, libraries: Vec<String>, manifest: Option<&Manifest>) -> Result<Self, String> {
+        let manifest_name = manifest.map(|manifest| format!("`[rainbow]` in {}", manifest.path.display()));
+
+        let (path, path_origin) = match (path, env::var("RAINBOW_PATH")) {
+            (Some(path), _) => (path, "--rainbow".to_string()),
+            (None, Ok(path)) if !path.is_empty() => (path, "RAINBOW_PATH".to_string()),
+            _ => match manifest.map(|manifest| manifest.path("rainbow.path")).transpose()?.flatten() {
+                Some(path) => (path, manifest_name.clone().unwrap()),
+                None => ("rainbow".to_string(), String::new())
+            }
+        };
+        if path.contains('/') && !Path::new(&path).is_file() {
+            return Err(format!("The Rainbow VM {} does not exist, it was set by {}", path, path_origin));
+        }
+
+        let (libraries, libraries_origin) = match (libraries.is_empty(), env::var("RAINBOW_LIB")) {
+            (false, _) => (libraries, "--lib".to_string()),
+            (true, Ok(libraries)) if !libraries.is_empty() => (
+                libraries.split(':').filter(|dir| !dir.is_empty()).map(|dir| dir.to_string()).collect(),
+                "RAINBOW_LIB".to_string()
+            ),
+            _ => match manifest.and_then(|manifest| manifest.paths("rainbow.libraries")) {
+                Some(libraries) => (libraries, manifest_name.unwrap()),
+                None => return Err(format!(
+                    "No Rainbow library directory is set, use `--lib <dir>`, set RAINBOW_LIB, or add `libraries` to `[rainbow]` in {}",
+                    MANIFEST
+                ))
+            }
+        };
+        for library in libraries.iter() {
+            if !Path::new(library).is_dir() {
+                return Err(format!("The Rainbow library directory {} does not exist, it was set by {}", library, libraries_origin));
+            }
+        }
+
+        Ok(Self {
+            path,
+            libraries
+        })
+    }
+}
+
+/// `line` without its `# comment`, if any.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+/// The values of the setting with the value `text`, a string, a bare word, or an array of them.
+fn parse_value(text: &str) -> Result<Vec<String>, String> {
+    let inner = match text.strip_prefix('[') {
+        Some(inner) => match inner.strip_suffix(']') {
+            Some(inner) => inner,
+            None => return Err("Unterminated array".to_string())
+        },
+        None => return Ok(vec![parse_scalar(text)?])
+    };
+
+    let mut values = vec![];
+    let mut item = String::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in inner.chars() {
+        match c {
+            _ if escaped => {
+                escaped = false;
+                item.push(c);
+            }
+            '\\' if in_string => {
+                escaped = true;
+                item.push(c);
+            }
+            '"' => {
+                in_string = !in_string;
+                item.push(c);
+            }
+            ',' if !in_string => {
+                values.push(parse_scalar(&item)?);
+                item.clear();
+            }
+            _ => item.push(c)
+        }
+    }
+    // A trailing comma is allowed.
+    if !item.trim().is_empty() {
+        values.push(parse_scalar(&item)?);
+    }
+
+    Ok(values)
+}
+
+fn parse_scalar(text: &str) -> Result<String, String> {
+    let text = text.trim();
+    if let Some(string) = text.strip_prefix('"') {
+        return match string.strip_suffix('"') {
+            Some(string) => Ok(string.replace("\\\"", "\"").replace("\\\\", "\\")),
+            None => Err(format!("Unterminated string {}", text))
+        };
+    }
+
+    if text.is_empty() || text.contains(char::is_whitespace) {
+        return Err(format!("Expected a value, got `{}`", text));
+    }
+    Ok(text.to_string())
+}
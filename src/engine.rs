@@ -16,10 +16,1107 @@ fn create_rainbow_string(s: String, wrapper: &mut Wrapper) {
     wrapper.push(bytes);
 }
 
+/// Maps a numeric `MType` to the Rainbow type of the same width.
+fn numeric_type(typ: &MType) -> Type {
+    match typ {
+        MType::Char => Type::U8,
+        MType::Number | MType::I64 => Type::I64,
+        MType::I32 => Type::I32,
+        MType::I16 => Type::I16,
+        MType::I8 => Type::I8,
+        MType::U64 => Type::U64,
+        MType::U32 => Type::U32,
+        MType::U16 => Type::U16,
+        MType::U8 => Type::U8,
+        MType::F64 => Type::F64,
+        MType::F32 => Type::F32,
+        MType::F16 => Type::F16,
+        _ => panic!("Cannot cast to non-numeric type: {:?}", typ)
+    }
+}
+
+/// Name of the scratch variable that holds the result of a cast to `typ`.
+fn cast_temp_name(typ: &MType) -> String {
+    format!("temp_{}", typ.stringify())
+}
+
+/// Records the type of a variable in the engine state.
+///
+/// Variables are keyed as `let <name>`, which can never collide with a function name, since those
+/// are looked up as plain identifiers.
+fn set_var_type(state: &mut HashMap<String, String>, name: &String, typ: &str) {
+    state.insert(format!("let {}", name), typ.to_string());
+}
+
+fn get_var_type(state: &HashMap<String, String>, name: &String) -> Option<String> {
+    state.get(&format!("let {}", name)).cloned()
+}
+
+/// Sets a compiler option for the engine, such as `unchecked` or `file`.
+///
+/// Options are keyed as `option <name>`, for the same reason variables are keyed as `let <name>`.
+pub fn set_option(state: &mut HashMap<String, String>, name: &str, value: &str) {
+    state.insert(format!("option {}", name), value.to_string());
+}
+
+fn get_option(state: &HashMap<String, String>, name: &str) -> Option<String> {
+    state.get(&format!("option {}", name)).cloned()
+}
+
+/// Whether `expr` evaluates to a string.
+fn is_string(expr: &Expr, state: &HashMap<String, String>) -> bool {
+    if let Some(call) = resolve_call(expr, state) {
+        return is_string(&call, state);
+    }
+
+    match expr {
+        Expr::String(_) | Expr::Interpolation(_) => true,
+        Expr::Identifier(name) => get_var_type(state, name) == Some("string".to_string()),
+        Expr::ArrayAccess(_, _, _) => value_kind(expr, state) == "string",
+        Expr::PropertyAccess(obj, _) if is_array(obj, state) || is_map(obj, state) || is_tuple(obj, state) => value_kind(expr, state) == "string",
+        Expr::BinOp(Operator::Add, left, right) => is_string(left, state) || is_string(right, state),
+        Expr::CallFunc(name, _) => {
+            match &**name {
+                Expr::Identifier(name) if function_returns(name, state).is_some() => {
+                    function_returns(name, state) == Some("string".to_string())
+                }
+                Expr::Identifier(name) => state.get(name) == Some(&"string".to_string()),
+                _ => false
+            }
+        }
+        Expr::PropertyAccess(obj, prop) => {
+            match (&**obj, &**prop) {
+                (Expr::Identifier(obj), Expr::CallFunc(name, _)) => {
+                    match &**name {
+                        Expr::Identifier(name) => {
+                            state.get(&format!("{}.{}", obj, name)) == Some(&"string".to_string())
+                        }
+                        _ => false
+                    }
+                }
+                _ => false
+            }
+        }
+        _ => false
+    }
+}
+
+/// Pushes a string operand onto the stack, as the struct that the string runtime expects.
+fn push_string_operand(expr: &Expr, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    match expr {
+        Expr::String(s) => {
+            // `string.new` leaves the struct on the stack for us.
+            create_rainbow_string(s.to_string(), wrapper);
+        }
+        Expr::Identifier(name) => {
+            wrapper.push(push!(ident!(name.clone())));
+        }
+        _ => {
+            // String results are stored in `temp_struct`
+            eval(vec![expr.clone()], wrapper, state);
+            wrapper.push(push!(ident!("temp_struct")));
+        }
+    }
+}
+
+/// Builds a scope that prints `message` and exits the program with code `1`.
+fn gen_abort(message: Expr, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) -> Vec<u8> {
+    let mut fail_wrapper = Wrapper::new();
+    match message {
+        Expr::String(s) => {
+            create_rainbow_string(s, &mut fail_wrapper);
+            fail_wrapper.push(pop!(ident!("temp_struct")));
+        }
+        _ => eval(vec![message], &mut fail_wrapper, state)
+    }
+    fail_wrapper.push(push!(ident!("temp_struct.text")));
+    fail_wrapper.push(push!(ident!("temp_struct.length")));
+    fail_wrapper.push(call!(name!("io.println")));
+    fail_wrapper.push(push!(immediate!(SIGNED(1))));
+    fail_wrapper.push(call!(name!("process.exit")));
+    wrapper.merge_data(&fail_wrapper);
+
+    return generate_scope(&fail_wrapper.bytes);
+}
+
+/// Emits a check that the index in `temp` is within `0..length`, aborting the program otherwise.
+/// Nothing is emitted when compiling with `--unchecked`.
+///
+/// Clobbers `temp_check`. `what` is the name of the indexed value and `line` is where it is
+/// indexed, for the error message.
+fn gen_bounds_check(what: &String, line: usize, length: Value, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    if get_option(state, "unchecked").is_some() {
+        return;
+    }
+
+    let file = get_option(state, "file").unwrap_or("<unknown>".to_string());
+
+    let mut fail_wrapper = Wrapper::new();
+    fail_wrapper.push(mov!(length.clone(), ident!("temp2")));
+    let message = Expr::Interpolation(Box::new(vec![
+        Expr::String(format!("{}:{}: Index ", file, line)),
+        Expr::Identifier("temp".to_string()),
+        Expr::String(format!(" is out of bounds for `{}` of length ", what)),
+        Expr::Identifier("temp2".to_string()),
+    ]));
+    let fail_scope = [fail_wrapper.bytes.clone(), gen_abort(message, wrapper, state)].concat();
+
+    let merged_scopes = [
+        cmp!(cond!(>=), ident!("temp"), immediate!(SIGNED(0)), ident!("temp_check")),
+        jne!(ident!("temp_check"), immediate!(SIGNED(1)), immediate!(SIGNED(5))), // what index to jump to
+        cmp!(cond!(<), ident!("temp"), length, ident!("temp_check")),
+        jne!(ident!("temp_check"), immediate!(SIGNED(1)), immediate!(SIGNED(5))),
+        jmp!(immediate!(SIGNED(6))), // In bounds, skip the failure scope
+        generate_scope(&fail_scope),
+    ].concat();
+
+    wrapper.push(generate_scope(&merged_scopes));
+}
+
+/// Declares the scratch variables the engine relies on, and emits the runtime functions that
+/// compiled code calls into. Must be pushed before anything else.
+pub fn gen_preamble(wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    for name in ["temp", "temp2", "temp3", "temp_check", "temp_pointer", "temp_array"] {
+        wrapper.push(var!(
+            Value::TYPE(vec![Type::I64]),
+            Value::NAME(name.to_string())
+        ));
+    }
+    wrapper.push(var!(
+        Value::TYPE(vec![Type::STRUCT("_".to_string())]),
+        Value::NAME("temp_struct".to_string())
+    ));
+
+    gen_array_runtime(wrapper, state);
+    gen_map_runtime(wrapper, state);
+
+    // Function values are called through these, see `gen_function_value`.
+    wrapper.push(var!(Value::TYPE(vec![Type::NAME]), Value::NAME("temp_function".to_string())));
+    wrapper.push(local("closure_env"));
+
+    // Casts move their value into one of these, see `Expr::Cast` in `eval`.
+    let numeric = [
+        MType::Number, MType::Char,
+        MType::I64, MType::I32, MType::I16, MType::I8,
+        MType::U64, MType::U32, MType::U16, MType::U8,
+        MType::F64, MType::F32, MType::F16
+    ];
+    for typ in numeric.iter() {
+        wrapper.push(var!(Value::TYPE(vec![numeric_type(typ)]), Value::NAME(cast_temp_name(typ))));
+    }
+}
+
+fn local(name: &str) -> Vec<u8> {
+    var!(Value::TYPE(vec![Type::I64]), Value::NAME(name.to_string()))
+}
+
+fn i64_arg(name: &str) -> Arg {
+    Arg {
+        name: name.to_string(),
+        typ: vec![Type::I64]
+    }
+}
+
+/// Emits `myst_array_new`, `myst_array_push` and `myst_array_pop`.
+///
+/// An array is a pointer to a header of three `I64` slots: `[length, capacity, data]`, where
+/// `data` points to `capacity` elements. Growing an array only replaces `data`, so every copy of
+/// the header pointer sees the new elements.
+fn gen_array_runtime(wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    // myst_array_new(capacity): allocates an empty array with room for `capacity` elements.
+    let mut body = Wrapper::new();
+    body.push(local("header"));
+    body.push(local("data"));
+    body.push(alloc!(rbtype!(I64), immediate!(SIGNED(3)), ident!("header")));
+    body.push(alloc!(rbtype!(I64), ident!("capacity"), ident!("data")));
+    body.push(pmov!(immediate!(SIGNED(0)), ident!("header"), immediate!(SIGNED(0))));
+    body.push(pmov!(ident!("capacity"), ident!("header"), immediate!(SIGNED(1))));
+    body.push(pmov!(ident!("data"), ident!("header"), immediate!(SIGNED(2))));
+    body.push(ret!(ident!("header")));
+    wrapper.push(generate_function(&"myst_array_new".to_string(), &vec![i64_arg("capacity")], &vec![Type::I64], &body.bytes));
+    state.insert("myst_array_new".to_string(), "number".to_string());
+
+    // myst_array_push(array, value): appends `value`, doubling the capacity when full. Returns the
+    // new length.
+    let mut body = Wrapper::new();
+    for name in ["length", "capacity", "data", "new_data", "pointer", "check", "i", "element"] {
+        body.push(local(name));
+    }
+    body.push(self::deref!(ident!("array"), ident!("length")));
+    body.push(add!(ident!("array"), immediate!(SIGNED(1)), ident!("pointer")));
+    body.push(self::deref!(ident!("pointer"), ident!("capacity")));
+    body.push(add!(ident!("array"), immediate!(SIGNED(2)), ident!("pointer")));
+    body.push(self::deref!(ident!("pointer"), ident!("data")));
+
+    let copy_body = [
+        add!(ident!("data"), ident!("i"), ident!("pointer")),
+        self::deref!(ident!("pointer"), ident!("element")),
+        pmov!(ident!("element"), ident!("new_data"), ident!("i")),
+        add!(ident!("i"), immediate!(SIGNED(1)), ident!("i")),
+    ].concat();
+    let copy_loop = [
+        generate_scope(&cmp!(cond!(<), ident!("i"), ident!("length"), ident!("check"))),
+        jne!(ident!("check"), immediate!(SIGNED(1)), immediate!(SIGNED(4))),
+        generate_scope(&copy_body),
+        jmp!(immediate!(SIGNED(0))),
+    ].concat();
+    let grow_body = [
+        mul!(ident!("capacity"), immediate!(SIGNED(2)), ident!("capacity")),
+        alloc!(rbtype!(I64), ident!("capacity"), ident!("new_data")),
+        mov!(immediate!(SIGNED(0)), ident!("i")),
+        generate_scope(&copy_loop),
+        mov!(ident!("new_data"), ident!("data")),
+        pmov!(ident!("capacity"), ident!("array"), immediate!(SIGNED(1))),
+        pmov!(ident!("data"), ident!("array"), immediate!(SIGNED(2))),
+    ].concat();
+    body.push(generate_scope(&[
+        cmp!(cond!(==), ident!("length"), ident!("capacity"), ident!("check")),
+        jne!(ident!("check"), immediate!(SIGNED(1)), immediate!(SIGNED(3))), // Not full, skip growing
+        generate_scope(&grow_body),
+    ].concat()));
+
+    body.push(pmov!(ident!("value"), ident!("data"), ident!("length")));
+    body.push(add!(ident!("length"), immediate!(SIGNED(1)), ident!("length")));
+    body.push(pmov!(ident!("length"), ident!("array"), immediate!(SIGNED(0))));
+    body.push(ret!(ident!("length")));
+    wrapper.push(generate_function(&"myst_array_push".to_string(), &vec![i64_arg("array"), i64_arg("value")], &vec![Type::I64], &body.bytes));
+    state.insert("myst_array_push".to_string(), "number".to_string());
+
+    // myst_array_pop(array): removes and returns the last element.
+    let mut body = Wrapper::new();
+    for name in ["length", "data", "pointer", "check", "element"] {
+        body.push(local(name));
+    }
+    body.push(self::deref!(ident!("array"), ident!("length")));
+    body.push(sub!(ident!("length"), immediate!(SIGNED(1)), ident!("length")));
+
+    let fail_scope = gen_abort(Expr::String("Cannot pop from an empty array".to_string()), &mut body, state);
+    body.push(generate_scope(&[
+        cmp!(cond!(>=), ident!("length"), immediate!(SIGNED(0)), ident!("check")),
+        jne!(ident!("check"), immediate!(SIGNED(1)), immediate!(SIGNED(3))),
+        jmp!(immediate!(SIGNED(4))),
+        fail_scope,
+    ].concat()));
+
+    body.push(pmov!(ident!("length"), ident!("array"), immediate!(SIGNED(0))));
+    body.push(add!(ident!("array"), immediate!(SIGNED(2)), ident!("pointer")));
+    body.push(self::deref!(ident!("pointer"), ident!("data")));
+    body.push(add!(ident!("data"), ident!("length"), ident!("pointer")));
+    body.push(self::deref!(ident!("pointer"), ident!("element")));
+    body.push(ret!(ident!("element")));
+    wrapper.merge_data(&body);
+    wrapper.push(generate_function(&"myst_array_pop".to_string(), &vec![i64_arg("array")], &vec![Type::I64], &body.bytes));
+    state.insert("myst_array_pop".to_string(), "number".to_string());
+}
+
+/// Emits `myst_map_new`, and `myst_map_find`, `myst_map_set` and `myst_map_remove` for both
+/// `number` and `string` keys, suffixed with the key kind.
+///
+/// A map is a pointer to two arrays, `[keys, values]`, kept in insertion order. Lookups scan the
+/// keys from the start, which is fine for the handful of headers or parameters a map usually holds.
+fn gen_map_runtime(wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    // myst_map_new(): allocates an empty map.
+    let mut body = Wrapper::new();
+    body.push(local("header"));
+    body.push(local("array"));
+    body.push(alloc!(rbtype!(I64), immediate!(SIGNED(2)), ident!("header")));
+    for slot in [0, 1] {
+        body.push(push!(immediate!(SIGNED(4))));
+        body.push(call!(name!("myst_array_new")));
+        body.push(pop!(ident!("array")));
+        body.push(pmov!(ident!("array"), ident!("header"), immediate!(SIGNED(slot))));
+    }
+    body.push(ret!(ident!("header")));
+    wrapper.push(generate_function(&"myst_map_new".to_string(), &vec![], &vec![Type::I64], &body.bytes));
+    state.insert("myst_map_new".to_string(), "number".to_string());
+
+    for kind in ["number", "string"] {
+        gen_map_find(kind, wrapper, state);
+        gen_map_set(kind, wrapper, state);
+        gen_map_remove(kind, wrapper, state);
+    }
+}
+
+/// Leaves the string in the `[text, length]` record `record` on the stack. Clobbers `pointer`,
+/// `text` and `size`.
+fn push_boxed_string(record: &str) -> Vec<u8> {
+    [
+        self::deref!(ident!(record), ident!("text")),
+        add!(ident!(record), immediate!(SIGNED(1)), ident!("pointer")),
+        self::deref!(ident!("pointer"), ident!("size")),
+        push!(ident!("text")),
+        push!(ident!("size")),
+        call!(name!("string.new")),
+    ].concat()
+}
+
+/// myst_map_find_<kind>(map, key): returns the index of `key`, or `-1` if it is not in the map.
+fn gen_map_find(kind: &str, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    let mut body = Wrapper::new();
+    for name in ["keys", "length", "data", "i", "pointer", "element", "check", "text", "size"] {
+        body.push(local(name));
+    }
+    body.push(self::deref!(ident!("map"), ident!("keys")));
+    body.push(self::deref!(ident!("keys"), ident!("length")));
+    body.push(add!(ident!("keys"), immediate!(SIGNED(2)), ident!("pointer")));
+    body.push(self::deref!(ident!("pointer"), ident!("data")));
+    body.push(mov!(immediate!(SIGNED(0)), ident!("i")));
+
+    // String keys are stored as `[text, length]` records, and compared by their contents.
+    let compare = match kind {
+        "string" => [
+            push_boxed_string("element"),
+            push_boxed_string("key"),
+            call!(name!("string.equals")),
+            pop!(ident!("check")),
+        ].concat(),
+        _ => cmp!(cond!(==), ident!("element"), ident!("key"), ident!("check"))
+    };
+
+    let loop_body = [
+        add!(ident!("data"), ident!("i"), ident!("pointer")),
+        self::deref!(ident!("pointer"), ident!("element")),
+        compare,
+        generate_scope(&[
+            jne!(ident!("check"), immediate!(SIGNED(1)), immediate!(SIGNED(2))), // Not this one, keep going
+            ret!(ident!("i")),
+        ].concat()),
+        add!(ident!("i"), immediate!(SIGNED(1)), ident!("i")),
+    ].concat();
+    body.push(generate_scope(&[
+        generate_scope(&cmp!(cond!(<), ident!("i"), ident!("length"), ident!("check"))),
+        jne!(ident!("check"), immediate!(SIGNED(1)), immediate!(SIGNED(4))),
+        generate_scope(&loop_body),
+        jmp!(immediate!(SIGNED(0))),
+    ].concat()));
+    body.push(ret!(immediate!(SIGNED(-1))));
+
+    let name = format!("myst_map_find_{}", kind);
+    wrapper.push(generate_function(&name, &vec![i64_arg("map"), i64_arg("key")], &vec![Type::I64], &body.bytes));
+    state.insert(name, "number".to_string());
+}
+
+/// myst_map_set_<kind>(map, key, value): sets `key` to `value`, adding it if it is not in the map
+/// yet. Returns the map, so that literals can chain calls without a scratch variable.
+fn gen_map_set(kind: &str, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    let mut body = Wrapper::new();
+    for name in ["index", "keys", "values", "data", "pointer", "check"] {
+        body.push(local(name));
+    }
+    body.push(push!(ident!("map")));
+    body.push(push!(ident!("key")));
+    body.push(call!(name!(format!("myst_map_find_{}", kind))));
+    body.push(pop!(ident!("index")));
+    body.push(self::deref!(ident!("map"), ident!("keys")));
+    body.push(add!(ident!("map"), immediate!(SIGNED(1)), ident!("pointer")));
+    body.push(self::deref!(ident!("pointer"), ident!("values")));
+
+    let append = [
+        push!(ident!("keys")),
+        push!(ident!("key")),
+        call!(name!("myst_array_push")),
+        pop!(ident!("check")),
+        push!(ident!("values")),
+        push!(ident!("value")),
+        call!(name!("myst_array_push")),
+        pop!(ident!("check")),
+    ].concat();
+    let update = [
+        add!(ident!("values"), immediate!(SIGNED(2)), ident!("pointer")),
+        self::deref!(ident!("pointer"), ident!("data")),
+        pmov!(ident!("value"), ident!("data"), ident!("index")),
+    ].concat();
+    body.push(generate_scope(&[
+        cmp!(cond!(==), ident!("index"), immediate!(SIGNED(-1)), ident!("check")),
+        jne!(ident!("check"), immediate!(SIGNED(1)), immediate!(SIGNED(4))), // Already there, update it
+        generate_scope(&append),
+        jmp!(immediate!(SIGNED(5))),
+        generate_scope(&update),
+    ].concat()));
+    body.push(ret!(ident!("map")));
+
+    let name = format!("myst_map_set_{}", kind);
+    wrapper.push(generate_function(&name, &vec![i64_arg("map"), i64_arg("key"), i64_arg("value")], &vec![Type::I64], &body.bytes));
+    state.insert(name, "number".to_string());
+}
+
+/// myst_map_remove_<kind>(map, key): removes `key` and its value, keeping the order of the rest.
+/// Returns `1` if the key was in the map, `0` otherwise.
+fn gen_map_remove(kind: &str, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    let mut body = Wrapper::new();
+    for name in ["index", "keys", "values", "length", "last", "j", "next", "pointer", "keys_data", "values_data", "element", "check"] {
+        body.push(local(name));
+    }
+    body.push(push!(ident!("map")));
+    body.push(push!(ident!("key")));
+    body.push(call!(name!(format!("myst_map_find_{}", kind))));
+    body.push(pop!(ident!("index")));
+    body.push(generate_scope(&[
+        cmp!(cond!(<), ident!("index"), immediate!(SIGNED(0)), ident!("check")),
+        jne!(ident!("check"), immediate!(SIGNED(1)), immediate!(SIGNED(3))), // Found, go remove it
+        ret!(immediate!(SIGNED(0))),
+    ].concat()));
+
+    body.push(self::deref!(ident!("map"), ident!("keys")));
+    body.push(add!(ident!("map"), immediate!(SIGNED(1)), ident!("pointer")));
+    body.push(self::deref!(ident!("pointer"), ident!("values")));
+    body.push(self::deref!(ident!("keys"), ident!("length")));
+    body.push(sub!(ident!("length"), immediate!(SIGNED(1)), ident!("last")));
+    body.push(add!(ident!("keys"), immediate!(SIGNED(2)), ident!("pointer")));
+    body.push(self::deref!(ident!("pointer"), ident!("keys_data")));
+    body.push(add!(ident!("values"), immediate!(SIGNED(2)), ident!("pointer")));
+    body.push(self::deref!(ident!("pointer"), ident!("values_data")));
+    body.push(mov!(ident!("index"), ident!("j")));
+
+    // Shift everything after the removed entry one slot to the left, then drop the last slot.
+    let shift = [
+        add!(ident!("j"), immediate!(SIGNED(1)), ident!("next")),
+        add!(ident!("keys_data"), ident!("next"), ident!("pointer")),
+        self::deref!(ident!("pointer"), ident!("element")),
+        pmov!(ident!("element"), ident!("keys_data"), ident!("j")),
+        add!(ident!("values_data"), ident!("next"), ident!("pointer")),
+        self::deref!(ident!("pointer"), ident!("element")),
+        pmov!(ident!("element"), ident!("values_data"), ident!("j")),
+        mov!(ident!("next"), ident!("j")),
+    ].concat();
+    body.push(generate_scope(&[
+        generate_scope(&cmp!(cond!(<), ident!("j"), ident!("last"), ident!("check"))),
+        jne!(ident!("check"), immediate!(SIGNED(1)), immediate!(SIGNED(4))),
+        generate_scope(&shift),
+        jmp!(immediate!(SIGNED(0))),
+    ].concat()));
+
+    for array in ["keys", "values"] {
+        body.push(push!(ident!(array)));
+        body.push(call!(name!("myst_array_pop")));
+        body.push(pop!(ident!("check")));
+    }
+    body.push(ret!(immediate!(SIGNED(1))));
+
+    let name = format!("myst_map_remove_{}", kind);
+    wrapper.push(generate_function(&name, &vec![i64_arg("map"), i64_arg("key")], &vec![Type::I64], &body.bytes));
+    state.insert(name, "number".to_string());
+}
+
+/// Lowers `for item of array`, `for (index, item) of array` and `for (key, value) of map` into a
+/// while loop over the indices of the array, or of the keys of the map. `line` is where the loop
+/// is, for the bounds checks of the elements it reads.
+fn gen_for(names: &Vec<String>, iterable: &Expr, body: &Vec<Expr>, line: usize, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    // Loops one after the other use the same counters, only nested loops need their own.
+    let depth = match state.get("for depth") {
+        Some(depth) => depth.parse::<usize>().unwrap(),
+        None => 0
+    };
+    state.insert("for depth".to_string(), (depth + 1).to_string());
+
+    let collection = format!("for_collection_{}", depth);
+    let index = format!("for_index_{}", depth);
+    let collection_expr = Expr::Identifier(collection.clone());
+    let index_expr = Expr::Identifier(index.clone());
+
+    let assign = |name: &String, value: Expr| Expr::BinOp(
+        Operator::Assign,
+        Box::new(Expr::Identifier(name.clone())),
+        Box::new(value)
+    );
+    let element = |array: &str| Expr::ArrayAccess(
+        Box::new(match array {
+            "" => collection_expr.clone(),
+            _ => Expr::PropertyAccess(Box::new(collection_expr.clone()), Box::new(Expr::Identifier(array.to_string())))
+        }),
+        Box::new(index_expr.clone()),
+        line
+    );
+
+    let collection_type = infer_type(iterable, state);
+    gen_loop_variable(&collection, collection_type, &value_kind(iterable, state), wrapper, state);
+    gen_loop_variable(&index, Type::I64, "number", wrapper, state);
+    eval(vec![assign(&collection, iterable.clone()), assign(&index, Expr::Number(0))], wrapper, state);
+
+    let values = match (is_map(&collection_expr, state), names.len()) {
+        (true, 1) => vec![element("keys")],
+        (true, 2) => vec![element("keys"), element("values")],
+        (false, 1) => vec![element("")],
+        (false, 2) => vec![index_expr.clone(), element("")],
+        _ => panic!("A for loop takes one or two variables, got {}", names.len())
+    };
+
+    // The loop variables are declared once up front, and assigned on every iteration.
+    let mut loop_body = vec![];
+    for (name, value) in names.iter().zip(values) {
+        let kind = value_kind(&value, state);
+        let typ = match kind.as_str() {
+            "string" => Type::STRUCT("_".to_string()),
+            _ => Type::I64
+        };
+        gen_loop_variable(name, typ, &kind, wrapper, state);
+
+        loop_body.push(assign(name, value));
+    }
+    loop_body.extend(body.iter().cloned());
+    loop_body.push(assign(&index, Expr::BinOp(Operator::Add, Box::new(index_expr.clone()), Box::new(Expr::Number(1)))));
+
+    let condition = Expr::BinOp(
+        Operator::Lesser,
+        Box::new(index_expr.clone()),
+        Box::new(Expr::PropertyAccess(Box::new(collection_expr.clone()), Box::new(Expr::Identifier("length".to_string()))))
+    );
+    eval(vec![Expr::While(Box::new(condition), Box::new(loop_body))], wrapper, state);
+    state.insert("for depth".to_string(), depth.to_string());
+}
+
+/// Declares the variable `name` of a for loop, holding values of `kind`. A variable of the same
+/// name and type declared by an earlier loop in the same function is used again instead.
+fn gen_loop_variable(name: &String, typ: Type, kind: &str, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    let function = state.get("in function").cloned().unwrap_or_default();
+    let declared = format!("loop variable {} {}", function, name);
+    let slot = format!("{:?}", typ);
+    if state.get(&declared) != Some(&slot) {
+        wrapper.push(var!(Value::TYPE(vec![typ]), Value::NAME(name.clone())));
+        state.insert(declared, slot);
+    }
+
+    set_var_type(state, name, kind);
+}
+
+/// Stores `value` under `key` in the map `target`.
+fn gen_map_store(target: &Expr, key: &Expr, value: &Expr, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) -> Vec<u8> {
+    let kind = key_kind(target, state);
+
+    eval(vec![target.clone()], wrapper, state);
+    wrapper.push(push!(ident!("temp")));
+    gen_element(key, wrapper, state);
+    wrapper.push(push!(ident!("temp")));
+    gen_element(value, wrapper, state);
+    wrapper.push(push!(ident!("temp")));
+    wrapper.push(call!(name!(format!("myst_map_set_{}", kind))));
+
+    pop!(ident!("temp"))
+}
+
+/// Loads the data pointer of the array `name` into `temp_pointer`.
+fn gen_array_data(name: &String, wrapper: &mut Wrapper) {
+    wrapper.push(add!(ident!(name), immediate!(SIGNED(2)), ident!("temp_pointer")));
+    wrapper.push(self::deref!(ident!("temp_pointer"), ident!("temp_pointer")));
+}
+
+/// Whether `typ` is `Array` or `Array<...>`.
+fn is_array_type(typ: &MType) -> bool {
+    match typ {
+        MType::Array => true,
+        MType::Nested(parent, _) => **parent == MType::Array,
+        _ => false
+    }
+}
+
+/// The kind of a variable holding a function of type `typ`, which is `fn` followed by the kind
+/// of value it returns. `None` if `typ` is not a function type.
+fn function_kind(typ: &MType) -> Option<String> {
+    let returns = match typ {
+        MType::Function => MType::Null,
+        MType::Nested(parent, returns) if **parent == MType::Function => *returns.clone(),
+        MType::Fn(_, returns) => *returns.clone(),
+        _ => return None
+    };
+
+    let kind = match returns {
+        MType::Undefined | MType::Null => "null".to_string(),
+        MType::Struct => "struct".to_string(),
+        t if function_kind(&t).is_some() => "function".to_string(),
+        t => type_kind(&t)
+    };
+    Some(format!("fn {}", kind))
+}
+
+/// The kind of value returned by calling the variable `name`, if it holds a function.
+fn function_returns(name: &String, state: &HashMap<String, String>) -> Option<String> {
+    match get_var_type(state, name) {
+        Some(kind) => kind.strip_prefix("fn ").map(|returns| returns.to_string()),
+        None => None
+    }
+}
+
+/// The Rainbow type a function declared to return `typ` returns.
+fn return_type(typ: &MType) -> Type {
+    match typ {
+        t if t.is_numeric() || *t == MType::Char => numeric_type(t),
+        _ => Type::I64
+    }
+}
+
+/// Whether values of `typ` live on the heap, and are passed around as a pointer in one `I64`.
+fn is_pointer_type(typ: &MType) -> bool {
+    match typ {
+        MType::Map(_, _) | MType::Tuple(_) => true,
+        _ => is_array_type(typ)
+    }
+}
+
+/// Whether `expr` evaluates to an array.
+fn is_array(expr: &Expr, state: &HashMap<String, String>) -> bool {
+    value_kind(expr, state).starts_with("array")
+}
+
+/// How a value of type `typ` is represented: `string`, `number` (anything that fits in a single
+/// slot) or `array <element kind>`. Variables are tagged with their kind through `set_var_type`.
+fn type_kind(typ: &MType) -> String {
+    match typ {
+        MType::String => "string".to_string(),
+        MType::Array => "array number".to_string(),
+        MType::Nested(parent, child) if **parent == MType::Array => format!("array {}", element_kind(child)),
+        MType::Map(key, value) => format!("map {} {}", type_kind(key), type_kind(value)),
+        MType::Tuple(types) => {
+            let kinds: Vec<String> = types.iter().map(|t| type_kind(t)).collect();
+            format!("tuple({})", kinds.join(", "))
+        }
+        t if function_kind(t).is_some() => function_kind(t).unwrap(),
+        _ => "number".to_string()
+    }
+}
+
+/// Like `type_kind`, but keeps sized numbers and `Char` apart, so that an instance of a generic
+/// function is compiled with the exact types it was checked with.
+fn instance_kind(typ: &MType) -> String {
+    match typ {
+        t if t.is_numeric() || *t == MType::Char => t.stringify(),
+        t => type_kind(t)
+    }
+}
+
+/// The type of values of `kind`, the reverse of `instance_kind`. Function kinds only know what
+/// the function returns.
+fn kind_type(kind: &str) -> MType {
+    let scalars = vec![
+        MType::Number, MType::I64, MType::I32, MType::I16, MType::I8,
+        MType::U64, MType::U32, MType::U16, MType::U8, MType::F64, MType::F32, MType::F16,
+        MType::String, MType::Char, MType::Struct, MType::Null
+    ];
+    if let Some(t) = scalars.into_iter().find(|t| t.stringify() == kind) {
+        return t;
+    }
+
+    if let Some(kinds) = tuple_kinds(kind) {
+        return MType::Tuple(kinds.iter().map(|kind| kind_type(kind)).collect());
+    }
+    if let Some((key, value)) = map_kinds(kind) {
+        return MType::Map(Box::new(kind_type(&key)), Box::new(kind_type(&value)));
+    }
+    if let Some(element) = kind.strip_prefix("array ") {
+        return MType::Nested(Box::new(MType::Array), Box::new(kind_type(element)));
+    }
+
+    match kind.strip_prefix("fn ") {
+        Some("function") => MType::Fn(vec![], Box::new(MType::Function)),
+        Some(returns) => MType::Fn(vec![], Box::new(kind_type(returns))),
+        None => panic!("Unknown kind: {}", kind)
+    }
+}
+
+/// Records that the generic function `name` is called with its type parameters bound to `types`,
+/// so that a copy of it is compiled for them. See `Checker::instances`.
+pub fn add_instance(state: &mut HashMap<String, String>, name: &String, types: &Vec<MType>) {
+    let kinds: Vec<String> = types.iter().map(|t| instance_kind(t)).collect();
+    let key = format!("instances {}", name);
+    let instances = match state.get(&key) {
+        Some(instances) => format!("{};{}", instances, kinds.join("|")),
+        None => kinds.join("|")
+    };
+    state.insert(key, instances);
+}
+
+/// Compiles a copy of the generic function `func` for every instance recorded with
+/// `add_instance`. The function itself is never compiled, there is no code for a type parameter.
+fn gen_instances(func: &Expr, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    let name = match func {
+        Expr::DecFunc(name, _, _, _) => name.clone(),
+        _ => panic!("Expected function, got {:?}", func)
+    };
+
+    let type_params = func.type_params();
+    let instances = state.get(&format!("instances {}", name)).cloned().unwrap_or_default();
+    let mut overloads = vec![];
+    for instance in instances.split(';').filter(|instance| !instance.is_empty()) {
+        let kinds: Vec<&str> = instance.split('|').collect();
+        let bindings: HashMap<String, MType> = type_params.iter().cloned()
+            .zip(kinds.iter().map(|kind| kind_type(kind)))
+            .collect();
+
+        let suffix: String = kinds.join("_").chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        let instance_name = format!("{}__{}", name, suffix);
+        let instance = func.instantiate(&instance_name, &bindings);
+
+        let param_kinds: Vec<String> = match &instance {
+            Expr::DecFunc(_, params, _, _) => params.iter().map(|param| match param {
+                Expr::Parameter(_, typ, _) | Expr::RestParameter(_, typ) => type_kind(typ),
+                _ => "number".to_string()
+            }).collect(),
+            _ => vec![]
+        };
+
+        // Registered before the body is compiled, so that recursive calls find it.
+        overloads.push(format!("{}={}", instance_name, param_kinds.join("|")));
+        state.insert(format!("overloads {}", name), overloads.join(";"));
+
+        eval(vec![instance], wrapper, state);
+    }
+}
+
+/// The call `expr` with the generic function it calls replaced by the instance taking arguments
+/// of the same kinds. `None` if `expr` does not call a generic function.
+fn resolve_call(expr: &Expr, state: &HashMap<String, String>) -> Option<Expr> {
+    let (name, args) = match expr {
+        Expr::CallFunc(callee, args) => match &**callee {
+            Expr::Identifier(name) => (name, args),
+            _ => return None
+        },
+        _ => return None
+    };
+
+    let overloads = state.get(&format!("overloads {}", name))?;
+    let arg_kinds: Vec<String> = package_arguments(name, args, state).iter().map(|arg| value_kind(arg, state)).collect();
+    let overloads: Vec<(&str, Vec<&str>)> = overloads.split(';')
+        .filter_map(|overload| overload.split_once('='))
+        .map(|(instance, kinds)| (instance, kinds.split('|').collect()))
+        .collect();
+
+    // Anonymous functions have no kind of their own, so any function matches them.
+    let instance = overloads.iter()
+        .find(|(_, param_kinds)| {
+            param_kinds.len() == arg_kinds.len()
+                && param_kinds.iter().zip(arg_kinds.iter()).all(|(param, arg)| param.starts_with("fn ") || param == arg)
+        })
+        .map(|(instance, _)| instance.to_string())
+        .unwrap_or_else(|| panic!("No instance of the generic function `{}` takes {:?}", name, arg_kinds));
+
+    Some(Expr::CallFunc(Box::new(Expr::Identifier(instance)), args.clone()))
+}
+
+/// The arguments of a call to the function `name`, with the ones for its rest parameter packaged
+/// into an array. A spread array is passed as it is.
+fn package_arguments(name: &String, args: &Vec<Expr>, state: &HashMap<String, String>) -> Vec<Expr> {
+    let fixed: usize = match state.get(&format!("variadic {}", name)) {
+        Some(fixed) => fixed.parse().unwrap(),
+        None => return args.clone()
+    };
+
+    let split = std::cmp::min(fixed, args.len());
+    let mut packaged = args[..split].to_vec();
+    match &args[split..] {
+        [Expr::Spread(array)] => packaged.push(*array.clone()),
+        rest => packaged.push(Expr::Array(Box::new(rest.to_vec())))
+    }
+    packaged
+}
+
+/// The kind of the elements of an array of `typ`. Every element takes a 64 bit slot, but integers
+/// narrower than that keep their type, so that stores wrap them to it, see `gen_array_element`.
+fn element_kind(typ: &MType) -> String {
+    match typ {
+        MType::I32 | MType::I16 | MType::I8 | MType::U32 | MType::U16 | MType::U8 | MType::Char => typ.stringify(),
+        _ => type_kind(typ)
+    }
+}
+
+/// The narrow integer type of elements of the kind `kind`, see `element_kind`.
+fn narrow_type(kind: &str) -> Option<MType> {
+    match kind {
+        "i32" => Some(MType::I32),
+        "i16" => Some(MType::I16),
+        "i8" => Some(MType::I8),
+        "u32" => Some(MType::U32),
+        "u16" => Some(MType::U16),
+        "u8" => Some(MType::U8),
+        "char" => Some(MType::Char),
+        _ => None
+    }
+}
+
+/// `value` with every element of an array literal stored as `kind` converted to its narrow
+/// type, in nested literals too.
+fn cast_elements(value: &Expr, kind: &str) -> Expr {
+    let element = match (value, kind.strip_prefix("array ")) {
+        (Expr::Array(_), Some(element)) => element,
+        _ => return value.clone()
+    };
+
+    value.map_children(&mut |item| match narrow_type(element) {
+        Some(typ) => Expr::Cast(Box::new(item.clone()), typ),
+        None => cast_elements(item, element)
+    })
+}
+
+/// Splits the kind of a tuple into the kinds of its values.
+fn tuple_kinds(kind: &str) -> Option<Vec<String>> {
+    let inner = kind.strip_prefix("tuple(")?.strip_suffix(")")?;
+
+    // Values may be tuples themselves, so only split on commas outside of parentheses.
+    let mut kinds = vec![];
+    let mut depth = 0;
+    let mut current = String::new();
+    for c in inner.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                kinds.push(current.trim().to_string());
+                current = String::new();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        kinds.push(current.trim().to_string());
+    }
+
+    Some(kinds)
+}
+
+/// Whether `expr` evaluates to a tuple.
+fn is_tuple(expr: &Expr, state: &HashMap<String, String>) -> bool {
+    tuple_kinds(&value_kind(expr, state)).is_some()
+}
+
+/// Splits the kind of a map into the kinds of its keys and values. Keys are always a single word,
+/// `string` or `number`.
+fn map_kinds(kind: &str) -> Option<(String, String)> {
+    let rest = kind.strip_prefix("map ")?;
+    let (key, value) = rest.split_once(' ')?;
+    Some((key.to_string(), value.to_string()))
+}
+
+/// Whether `expr` evaluates to a map.
+fn is_map(expr: &Expr, state: &HashMap<String, String>) -> bool {
+    map_kinds(&value_kind(expr, state)).is_some()
+}
+
+/// The kind of the keys of the map `expr`, which picks the runtime functions to call.
+fn key_kind(expr: &Expr, state: &HashMap<String, String>) -> String {
+    match map_kinds(&value_kind(expr, state)) {
+        Some((key, _)) => key,
+        None => panic!("Expected a map, got {:?}", expr)
+    }
+}
+
+/// Works out the kind of value `expr` evaluates to, see `type_kind`.
+fn value_kind(expr: &Expr, state: &HashMap<String, String>) -> String {
+    if let Some(call) = resolve_call(expr, state) {
+        return value_kind(&call, state);
+    }
+
+    match expr {
+        Expr::Array(items) => {
+            match items.first() {
+                Some(item) => format!("array {}", value_kind(item, state)),
+                None => "array number".to_string()
+            }
+        }
+        Expr::Map(entries) => {
+            match entries.first() {
+                Some((key, value)) => format!("map {} {}", value_kind(key, state), value_kind(value, state)),
+                None => "map number number".to_string()
+            }
+        }
+        Expr::Tuple(items) => {
+            let kinds: Vec<String> = items.iter().map(|item| value_kind(item, state)).collect();
+            format!("tuple({})", kinds.join(", "))
+        }
+        // Names in the state are functions, their values are called like variables holding them.
+        Expr::Identifier(name) if state.contains_key(name) => format!("fn {}", state.get(name).unwrap()),
+        Expr::Identifier(name) => get_var_type(state, name).unwrap_or("number".to_string()),
+        Expr::DecFunc(_, _, _, returns) => function_kind(&MType::Fn(vec![], Box::new(returns.clone()))).unwrap(),
+        Expr::Closure(func, _) => value_kind(func, state),
+        Expr::CallFunc(name, _) if !is_string(expr, state) => {
+            match &**name {
+                Expr::Identifier(name) if function_returns(name, state).is_some() => function_returns(name, state).unwrap(),
+                Expr::Identifier(name) => state.get(&format!("returns {}", name)).cloned().unwrap_or("number".to_string()),
+                _ => "number".to_string()
+            }
+        }
+        Expr::PropertyAccess(obj, prop) if is_tuple(obj, state) => {
+            let kinds = tuple_kinds(&value_kind(obj, state)).unwrap();
+            match &**prop {
+                Expr::Number(n) => kinds.get(*n as usize).cloned().unwrap_or("number".to_string()),
+                _ => "number".to_string()
+            }
+        }
+        Expr::ArrayAccess(target, _, _) => {
+            let target_kind = value_kind(target, state);
+            if let Some((_, value)) = map_kinds(&target_kind) {
+                return value;
+            }
+
+            // Indexing a string gives a `Char`, indexing an array gives one of its elements.
+            match target_kind.strip_prefix("array ") {
+                Some(element) => element.to_string(),
+                None => "number".to_string()
+            }
+        }
+        Expr::PropertyAccess(obj, prop) if is_map(obj, state) => {
+            let (key, value) = map_kinds(&value_kind(obj, state)).unwrap();
+            match &**prop {
+                Expr::Identifier(name) if name == "keys" => format!("array {}", key),
+                Expr::Identifier(name) if name == "values" => format!("array {}", value),
+                _ => "number".to_string()
+            }
+        }
+        Expr::PropertyAccess(obj, prop) if is_array(obj, state) => {
+            match &**prop {
+                Expr::CallFunc(name, _) => {
+                    match &**name {
+                        Expr::Identifier(name) if name == "pop" => {
+                            value_kind(&Expr::ArrayAccess(obj.clone(), Box::new(Expr::Number(0)), 0), state)
+                        }
+                        _ => "number".to_string()
+                    }
+                }
+                _ => "number".to_string()
+            }
+        }
+        _ if is_string(expr, state) => "string".to_string(),
+        _ => "number".to_string()
+    }
+}
+
+/// Copies the string in `temp_struct` into a new `[text, length]` record, and leaves the pointer
+/// to it in `temp`. Strings are stored in arrays this way, so that every element is one slot.
+fn gen_box_string(wrapper: &mut Wrapper) {
+    wrapper.push(alloc!(rbtype!(I64), immediate!(SIGNED(2)), ident!("temp")));
+    wrapper.push(pmov!(ident!("temp_struct.text"), ident!("temp"), immediate!(SIGNED(0))));
+    wrapper.push(pmov!(ident!("temp_struct.length"), ident!("temp"), immediate!(SIGNED(1))));
+}
+
+/// Turns the record pointed to by `temp` back into a string in `temp_struct`. Clobbers
+/// `temp_check`.
+fn gen_unbox_string(wrapper: &mut Wrapper) {
+    wrapper.push(add!(ident!("temp"), immediate!(SIGNED(1)), ident!("temp_check")));
+    wrapper.push(self::deref!(ident!("temp_check"), ident!("temp_check")));
+    wrapper.push(self::deref!(ident!("temp"), ident!("temp")));
+    wrapper.push(push!(ident!("temp")));
+    wrapper.push(push!(ident!("temp_check")));
+    wrapper.push(call!(name!("string.new")));
+    wrapper.push(pop!(ident!("temp_struct")));
+}
+
+/// Evaluates an element about to be stored in an array with elements of the kind `kind`, leaving
+/// it in `temp`. Narrow integers are converted to their type first, so they wrap like a variable
+/// of that type does.
+fn gen_array_element(value: &Expr, kind: &str, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    let value = match narrow_type(kind) {
+        Some(typ) => Expr::Cast(Box::new(value.clone()), typ),
+        None => cast_elements(value, kind)
+    };
+    gen_element(&value, wrapper, state);
+}
+
+/// The kind of the elements of the array `target`.
+fn array_element_kind(target: &Expr, state: &HashMap<String, String>) -> String {
+    let kind = value_kind(target, state);
+    kind.strip_prefix("array ").unwrap_or("number").to_string()
+}
+
+/// Evaluates `value`, and returns where it is left: `temp_struct` for strings, `temp` otherwise.
+fn gen_value(value: &Expr, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) -> &'static str {
+    match value {
+        Expr::String(s) => {
+            create_rainbow_string(s.to_string(), wrapper);
+            wrapper.push(pop!(ident!("temp_struct")));
+        }
+        Expr::Identifier(name) if is_string(value, state) => {
+            wrapper.push(mov!(ident!(name), ident!("temp_struct")));
+        }
+        _ => {
+            eval(vec![value.clone()], wrapper, state);
+        }
+    }
+
+    match is_string(value, state) {
+        true => "temp_struct",
+        false => "temp"
+    }
+}
+
+/// Evaluates an array element that is about to be stored, leaving it in `temp`.
+fn gen_element(value: &Expr, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    if gen_value(value, wrapper, state) == "temp_struct" {
+        gen_box_string(wrapper);
+    }
+}
+
+/// Evaluates `target` and `index`, and checks the index against the length of the array.
+///
+/// Leaves the index in `temp` and returns the variable holding the array, which is `temp_array`
+/// unless `target` is a plain variable.
+fn gen_array_index(target: &Expr, index: &Expr, line: usize, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) -> String {
+    let name = match target {
+        Expr::Identifier(name) => {
+            eval(vec![index.clone()], wrapper, state);
+            name.clone()
+        }
+        _ => {
+            // The index may index other arrays too, so keep the array on the stack meanwhile.
+            eval(vec![target.clone()], wrapper, state);
+            wrapper.push(push!(ident!("temp")));
+            eval(vec![index.clone()], wrapper, state);
+            wrapper.push(pop!(ident!("temp_array")));
+            "temp_array".to_string()
+        }
+    };
+
+    wrapper.push(self::deref!(ident!(name.clone()), ident!("temp_pointer")));
+    gen_bounds_check(&target.describe(), line, ident!("temp_pointer"), wrapper, state);
+
+    return name;
+}
+
+/// Lowers an operator on strings into calls to the string runtime.
+///
+/// `+` leaves the new string in `temp_struct`, comparisons leave `1` or `0` in `temp`.
+fn gen_string_op(op: Operator, left: Expr, right: Expr, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) -> Vec<u8> {
+    push_string_operand(&left, wrapper, state);
+    push_string_operand(&right, wrapper, state);
+
+    match op {
+        Operator::Add => {
+            wrapper.push(call!(name!("string.concat")));
+            pop!(ident!("temp_struct"))
+        }
+        Operator::Equality | Operator::NotEqual => {
+            wrapper.push(call!(name!("string.equals")));
+            wrapper.push(pop!(ident!("temp")));
+
+            match op {
+                Operator::NotEqual => cmp!(cond!(==), ident!("temp"), immediate!(SIGNED(0)), ident!("temp")),
+                _ => nop!()
+            }
+        }
+        _ => {
+            // `string.compare` returns a negative number, zero, or a positive number, like `strcmp`.
+            wrapper.push(call!(name!("string.compare")));
+            wrapper.push(pop!(ident!("temp")));
+
+            match op {
+                Operator::Lesser => cmp!(cond!(<), ident!("temp"), immediate!(SIGNED(0)), ident!("temp")),
+                Operator::Greater => cmp!(cond!(>), ident!("temp"), immediate!(SIGNED(0)), ident!("temp")),
+                Operator::LesserEqual => cmp!(cond!(<=), ident!("temp"), immediate!(SIGNED(0)), ident!("temp")),
+                Operator::GreaterEqual => cmp!(cond!(>=), ident!("temp"), immediate!(SIGNED(0)), ident!("temp")),
+                _ => panic!("Operator {:?} is not supported on strings", op)
+            }
+        }
+    }
+}
+
 fn infer_type(expr: &Expr, state: &HashMap<String, String>) -> Type {
+    if let Some(call) = resolve_call(expr, state) {
+        return infer_type(&call, state);
+    }
+
     match expr {
         Expr::Number(_) => Type::I64,
-        Expr::String(_) => Type::STRUCT("_".to_string()),
+        Expr::String(_) | Expr::Interpolation(_) => Type::STRUCT("_".to_string()),
         Expr::Identifier(name) => {
             match state.get(name) {
                 Some(t) => {
@@ -27,18 +1124,28 @@ fn infer_type(expr: &Expr, state: &HashMap<String, String>) -> Type {
                         "number" => Type::I64,
                         "string" => Type::STRUCT("_".to_string()),
                         "struct" => Type::STRUCT("_".to_string()),
-                        "callback" => Type::NAME,
                         "null" => Type::VOID,
                         _ => Type::I64
                     }
                 }
                 None => {
-                    panic!("Could not infer type of identifier: {}", name);
+                    match get_var_type(state, name) {
+                        Some(t) if t == "string" => Type::STRUCT("_".to_string()),
+                        Some(_) => Type::I64,
+                        None => panic!("Could not infer type of identifier: {}", name)
+                    }
                 }
             }
         }
         Expr::CallFunc(name, _) => {
             match *name.clone() {
+                Expr::Identifier(name) if function_returns(&name, state).is_some() => {
+                    match function_returns(&name, state).unwrap().as_str() {
+                        "string" | "struct" => Type::STRUCT("_".to_string()),
+                        "null" => Type::VOID,
+                        _ => Type::I64
+                    }
+                }
                 Expr::Identifier(name) => {
                     match state.get(&name) {
                         Some(t) => {
@@ -57,7 +1164,6 @@ fn infer_type(expr: &Expr, state: &HashMap<String, String>) -> Type {
                                 "f16" => Type::F16,
                                 "string" => Type::STRUCT("_".to_string()),
                                 "struct" => Type::STRUCT("_".to_string()),
-                                "callback" => Type::NAME,
                                 "null" => Type::VOID,
                                 _ => Type::I64
                             }
@@ -72,14 +1178,369 @@ fn infer_type(expr: &Expr, state: &HashMap<String, String>) -> Type {
                 }
             }
         }
-        Expr::Array(_) => Type::I64,
+        Expr::Array(_) | Expr::Map(_) | Expr::Tuple(_) => Type::I64,
+        Expr::PropertyAccess(item, _) if is_map(item, state) => Type::I64,
+        Expr::PropertyAccess(item, _) if is_string(item, state) => Type::I64,
+        Expr::PropertyAccess(item, _) if is_array(item, state) || is_tuple(item, state) => {
+            match is_string(expr, state) {
+                true => Type::STRUCT("_".to_string()),
+                false => Type::I64
+            }
+        }
+        Expr::Cast(_, typ) => numeric_type(typ),
+        Expr::Char(_) => Type::U8,
+        Expr::ArrayAccess(target, _, _) => {
+            if is_string(expr, state) {
+                Type::STRUCT("_".to_string())
+            } else if is_string(target, state) {
+                Type::U8
+            } else {
+                Type::I64
+            }
+        }
+        Expr::BinOp(_, _, _) => {
+            if is_string(expr, state) {
+                Type::STRUCT("_".to_string())
+            } else {
+                Type::I64
+            }
+        }
+        // Function values are pointers, see `gen_function_value`.
+        Expr::DecFunc(_, _, _, _) | Expr::Closure(_, _) => Type::I64,
         _ => {
             panic!("Could not infer type of expression: {:?}", expr);
         }
     }
 }
 
+/// Returns the tuple `value` from a function declared to return one, as separate values: every
+/// value but the last is pushed, and the last one is returned. The caller pops them all again.
+fn gen_return_values(value: &Expr, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    let finish = |source: &str, last: bool| match last {
+        true => ret!(ident!(source)),
+        false => push!(ident!(source))
+    };
+
+    match value {
+        Expr::Tuple(items) => {
+            for (i, item) in items.iter().enumerate() {
+                let source = gen_value(item, wrapper, state);
+                wrapper.push(finish(source, i + 1 == items.len()));
+            }
+        }
+        // A tuple kept in a variable, or built some other way, is read back value by value.
+        _ => {
+            let kinds = tuple_kinds(&value_kind(value, state))
+                .unwrap_or_else(|| panic!("Cannot return {:?} as a tuple, its type is not known", value));
+            eval(vec![value.clone()], wrapper, state);
+            wrapper.push(mov!(ident!("temp"), ident!("temp_array")));
+
+            for (i, kind) in kinds.iter().enumerate() {
+                wrapper.push(add!(ident!("temp_array"), immediate!(SIGNED(i as i64)), ident!("temp_pointer")));
+                wrapper.push(self::deref!(ident!("temp_pointer"), ident!("temp")));
+                let source = match kind.as_str() {
+                    "string" => {
+                        gen_unbox_string(wrapper);
+                        "temp_struct"
+                    }
+                    _ => "temp"
+                };
+                wrapper.push(finish(source, i + 1 == kinds.len()));
+            }
+        }
+    }
+}
+
+/// Puts the values returned by a function that returns several, which are on the stack, into a
+/// new tuple in `temp`, for when they are used as a single value.
+///
+/// Functions and their callers share one stack, so a function returning a tuple pushes every
+/// value but the last, in order, and returns the last one (see `gen_return_values`). The caller
+/// has to pop all of them right after the `call`, before it pushes anything else, either here or
+/// in `gen_destructure`.
+fn gen_collect_tuple(kinds: &Vec<String>, wrapper: &mut Wrapper) {
+    wrapper.push(alloc!(rbtype!(I64), immediate!(SIGNED(kinds.len() as i64)), ident!("temp3")));
+
+    // The last value is on top of the stack.
+    for (i, kind) in kinds.iter().enumerate().rev() {
+        match kind.as_str() {
+            "string" => {
+                wrapper.push(pop!(ident!("temp_struct")));
+                gen_box_string(wrapper);
+            }
+            _ => wrapper.push(pop!(ident!("temp")))
+        }
+        wrapper.push(pmov!(ident!("temp"), ident!("temp3"), immediate!(SIGNED(i as i64))));
+    }
+
+    wrapper.push(mov!(ident!("temp3"), ident!("temp")));
+}
+
+/// Lowers `let (a, b) = value`, `let [a, b] = value` and `let { a, b } = value` into a
+/// declaration of every name, reading its value out of `value`.
+fn gen_destructure(pattern: &Expr, typ: &MType, value: &Expr, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) -> Vec<u8> {
+    let declare = |name: &Expr, typ: MType, value: Expr| Expr::BinOp(
+        Operator::Declare(typ),
+        Box::new(name.clone()),
+        Box::new(value)
+    );
+
+    // A call returning several values leaves them on the stack, where they are popped straight
+    // into the names without building a tuple.
+    if let (Expr::Tuple(names), Expr::CallFunc(callee, args)) = (pattern, value) {
+        if let (Expr::Identifier(function), None) = (&**callee, resolve_call(value, state)) {
+            let returns = function_returns(function, state).or(state.get(function).cloned());
+            if let Some(kinds) = returns.as_deref().and_then(tuple_kinds) {
+                gen_call(function, args, wrapper, state);
+
+                let names: Vec<String> = names.iter().map(|name| match name {
+                    Expr::Identifier(name) => name.clone(),
+                    _ => panic!("Expected identifier, got {:?}", name)
+                }).collect();
+                for (name, kind) in names.iter().zip(kinds.iter()) {
+                    let typ = match kind.as_str() {
+                        "string" => Type::STRUCT("_".to_string()),
+                        _ => Type::I64
+                    };
+                    wrapper.push(var!(Value::TYPE(vec![typ]), Value::NAME(name.clone())));
+                    if kind != "number" {
+                        set_var_type(state, name, kind);
+                    }
+                }
+                for name in names.iter().take(kinds.len()).rev() {
+                    wrapper.push(pop!(ident!(name)));
+                }
+
+                return nop!();
+            }
+        }
+    }
+
+    // Anything but a variable is evaluated once, into a hidden variable.
+    let source = match value {
+        Expr::Identifier(_) => value.clone(),
+        _ => {
+            let count = match state.get("destructure count") {
+                Some(count) => count.parse::<usize>().unwrap() + 1,
+                None => 0
+            };
+            state.insert("destructure count".to_string(), count.to_string());
+
+            let source = Expr::Identifier(format!("destructure_{}", count));
+            eval(vec![declare(&source, typ.clone(), value.clone())], wrapper, state);
+            source
+        }
+    };
+
+    let reads: Vec<(Expr, Expr)> = match pattern {
+        Expr::Tuple(names) => names.iter().enumerate().map(|(i, name)| {
+            (name.clone(), Expr::PropertyAccess(Box::new(source.clone()), Box::new(Expr::Number(i as i64))))
+        }).collect(),
+        Expr::Array(names) => names.iter().map(|name| match name {
+            Expr::ArrayAccess(name, index, line) => (*name.clone(), Expr::ArrayAccess(Box::new(source.clone()), index.clone(), *line)),
+            _ => panic!("Expected identifier, got {:?}", name)
+        }).collect(),
+        Expr::Map(fields) => fields.iter().map(|(field, name)| {
+            (name.clone(), Expr::PropertyAccess(Box::new(source.clone()), Box::new(field.clone())))
+        }).collect(),
+        _ => panic!("Cannot destructure into {:?}", pattern)
+    };
+
+    for (name, read) in reads {
+        eval(vec![declare(&name, MType::Undefined, read)], wrapper, state);
+    }
+
+    nop!()
+}
+
+/// Pushes the arguments of the call to `name` and calls it, leaving what it returns on the
+/// stack. Returns the kind of value it returns, a tuple of them for several values.
+fn gen_call(name: &String, args: &Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) -> String {
+    let args = package_arguments(name, args, state);
+
+    // Evaluate the arguments
+    let mut i = 0;
+    while i < args.len() {
+        match &args[i] {
+            Expr::Number(n) => {
+                wrapper.push(push!(immediate!(SIGNED(*n))));
+            }
+            Expr::Identifier(name) => {
+                // Explanation for later me:
+                // If the `name` exists in our cope-state, it is a function.
+                // If it does not exist, it is a variable.
+                // Probably.
+                // TODO: Rework how the engine stores state.
+                match state.get(name) {
+                    Some(_t) => {
+                        gen_function_value(name, None, wrapper, state);
+                        wrapper.push(push!(ident!("temp")));
+                    }
+                    None => {
+                        wrapper.push(push!(ident!(name.clone())));
+                    }
+                };
+            }
+            Expr::String(s) => {
+                create_rainbow_string(s.to_string(), wrapper);
+                wrapper.push(pop!(ident!("temp_struct")));
+            }
+            Expr::BinOp(_, _, _) | Expr::Interpolation(_) | Expr::ArrayAccess(_, _, _) => {
+                let result = match is_string(&args[i], state) {
+                    true => "temp_struct",
+                    false => "temp"
+                };
+                eval(vec![args[i].clone()], wrapper, state);
+                let bytes = push!(ident!(result));
+                wrapper.push(bytes);
+            }
+            Expr::DecFunc(name, _, _, _typ) => {
+                eval(vec![args[i].clone()], wrapper, state);
+                gen_function_value(name, None, wrapper, state);
+                wrapper.push(push!(ident!("temp")));
+            }
+            Expr::Closure(func, captures) => {
+                gen_closure(func, captures, wrapper, state);
+                wrapper.push(push!(ident!("temp")));
+            }
+            _ => {
+                // arg is stored in `temp`
+                eval(vec![args[i].clone()], wrapper, state);
+                let bytes = push!(ident!("temp"));
+                wrapper.push(bytes);
+            }
+        }
+
+        i += 1;
+    }
+
+    // Variables holding a function are called through the name they hold.
+    match function_returns(name, state) {
+        Some(returns) => {
+            gen_function_call(name, wrapper);
+            returns
+        }
+        None => {
+            wrapper.push(call!(name!(name)));
+            match state.get(name) {
+                Some(t) => t.clone(),
+                None => MType::Undefined.stringify()
+            }
+        }
+    }
+}
+
+/// Builds the value of the function `name` into `temp`: a pointer to its name and the tuple of
+/// the variables it captured, or `0` for functions that capture nothing.
+///
+/// A call through the value puts the tuple in `closure_env` right before calling the function,
+/// see `gen_function_call`.
+fn gen_function_value(name: &String, environment: Option<&Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    match environment {
+        Some(environment) => eval(vec![environment.clone()], wrapper, state),
+        None => wrapper.push(mov!(immediate!(SIGNED(0)), ident!("temp")))
+    }
+    wrapper.push(push!(ident!("temp")));
+
+    wrapper.push(alloc!(rbtype!(I64), immediate!(SIGNED(2)), ident!("temp")));
+    wrapper.push(mov!(immediate!(NAME(name.clone())), ident!("temp_function")));
+    wrapper.push(pmov!(ident!("temp_function"), ident!("temp"), immediate!(SIGNED(0))));
+    wrapper.push(pop!(ident!("temp3")));
+    wrapper.push(pmov!(ident!("temp3"), ident!("temp"), immediate!(SIGNED(1))));
+}
+
+/// Calls the function value in the variable `name`, with its arguments already pushed.
+fn gen_function_call(name: &String, wrapper: &mut Wrapper) {
+    wrapper.push(self::deref!(ident!(name), ident!("temp_function")));
+    wrapper.push(add!(ident!(name), immediate!(SIGNED(1)), ident!("temp_pointer")));
+    wrapper.push(self::deref!(ident!("temp_pointer"), ident!("closure_env")));
+    wrapper.push(call!(ident!("temp_function")));
+}
+
+/// Compiles the anonymous function `func`, and builds its value into `temp` with the current
+/// values of `captures`.
+///
+/// Every time the closure is created, the captured values go into a new tuple, so closures
+/// created by the same code, in a loop or through recursion, each keep their own. The function
+/// starts by reading them back from `closure_env` into variables of the same names.
+fn gen_closure(func: &Expr, captures: &Vec<String>, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
+    let names: Vec<Expr> = captures.iter().map(|name| Expr::Identifier(name.clone())).collect();
+    let environment = Expr::Tuple(Box::new(names.clone()));
+
+    let (name, params, body, typ) = match func {
+        Expr::DecFunc(name, params, body, typ) => (name, params, body, typ),
+        _ => panic!("Expected function, got {:?}", func)
+    };
+
+    // The function is compiled once, however many closures are created from it.
+    if !state.contains_key(name) {
+        // `closure_env` holds a different tuple for every closure, its kind is set for this one.
+        set_var_type(state, &"closure_env".to_string(), &value_kind(&environment, state));
+
+        let mut closure_body = vec![
+            Expr::BinOp(
+                Operator::Declare(MType::Undefined),
+                Box::new(Expr::Tuple(Box::new(names))),
+                Box::new(Expr::Identifier("closure_env".to_string()))
+            ),
+        ];
+        closure_body.extend(body.iter().cloned());
+
+        let func = Expr::DecFunc(name.clone(), params.clone(), Box::new(closure_body), typ.clone());
+        eval(vec![func], wrapper, state);
+    }
+
+    gen_function_value(name, Some(&environment), wrapper, state);
+}
+
+/// Stores `value` into `target[index]`, after checking the index against the array length.
+fn gen_array_store(target: &Expr, index: &Expr, line: usize, value: &Expr, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) -> Vec<u8> {
+    gen_array_element(value, &array_element_kind(target, state), wrapper, state);
+    wrapper.push(push!(ident!("temp")));
+
+    let name = gen_array_index(target, index, line, wrapper, state);
+
+    gen_array_data(&name, wrapper);
+    wrapper.push(pop!(ident!("temp_check")));
+
+    pmov!(ident!("temp_check"), ident!("temp_pointer"), ident!("temp"))
+}
+
 fn gen_cmp(op: Operator, left: Expr, right: Expr, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) -> Vec<u8> {
+    match op {
+        Operator::Add | Operator::Equality | Operator::NotEqual
+        | Operator::Lesser | Operator::Greater | Operator::LesserEqual | Operator::GreaterEqual => {
+            if is_string(&left, state) || is_string(&right, state) {
+                return gen_string_op(op, left, right, wrapper, state);
+            }
+        }
+        _ => {}
+    }
+
+    match (&op, &left) {
+        (Operator::Assign, Expr::ArrayAccess(target, key, _)) if is_map(target, state) => {
+            return gen_map_store(target, key, &right, wrapper, state);
+        }
+        (Operator::Assign, Expr::ArrayAccess(name, index, line)) => {
+            return gen_array_store(name, index, *line, &right, wrapper, state);
+        }
+        _ => {}
+    }
+
+    match (&op, &left) {
+        (Operator::Declare(typ), Expr::Tuple(_) | Expr::Array(_) | Expr::Map(_)) => {
+            return gen_destructure(&left, typ, &right, wrapper, state);
+        }
+        _ => {}
+    }
+
+    // Literals stored as arrays of narrow integers are converted element by element.
+    let right = match (&op, &left) {
+        (Operator::Declare(typ), _) if *typ != MType::Undefined => cast_elements(&right, &type_kind(typ)),
+        (Operator::Assign, Expr::Identifier(_)) => cast_elements(&right, &value_kind(&left, state)),
+        _ => right
+    };
+
     match op {
         Operator::Declare(ref typ) => {
             let name = match left {
@@ -87,6 +1548,14 @@ fn gen_cmp(op: Operator, left: Expr, right: Expr, wrapper: &mut Wrapper, state:
                 _ => todo!()
             };
 
+            let kind = match typ {
+                MType::Undefined => value_kind(&right, state),
+                _ => type_kind(typ)
+            };
+            if kind != "number" {
+                set_var_type(state, name, &kind);
+            }
+
             let typ = match typ {
                 MType::Number => {
                     Value::TYPE(vec![Type::I64])
@@ -134,6 +1603,15 @@ fn gen_cmp(op: Operator, left: Expr, right: Expr, wrapper: &mut Wrapper, state:
                 MType::Struct | MType::String => {
                     Value::TYPE(vec![Type::STRUCT("_".to_string())])
                 }
+                MType::Char => {
+                    Value::TYPE(vec![Type::U8])
+                }
+                t if is_pointer_type(t) => {
+                    Value::TYPE(vec![Type::I64])
+                }
+                t if function_kind(t).is_some() => {
+                    Value::TYPE(vec![Type::I64])
+                }
                 MType::Null => {
                     Value::TYPE(vec![Type::VOID])
                 }
@@ -154,7 +1632,13 @@ fn gen_cmp(op: Operator, left: Expr, right: Expr, wrapper: &mut Wrapper, state:
 
     let left_macro = match left {
         Expr::Number(n) => immediate!(SIGNED(n)),
+        Expr::Char(c) => immediate!(SIGNED(c as i64)),
         Expr::Identifier(ref i) => ident!(i),
+        Expr::PropertyAccess(ref obj, _) if (is_array(obj, state) || is_map(obj, state) || is_tuple(obj, state)) && !is_string(&left, state) => {
+            eval(vec![left.clone()], wrapper, state);
+            wrapper.push(mov!(ident!("temp"), ident!("temp2".to_string())));
+            ident!("temp2")
+        }
         Expr::PropertyAccess(_, ref prop) => {
             match **prop {
                 Expr::CallFunc(_, _) => {
@@ -163,6 +1647,7 @@ fn gen_cmp(op: Operator, left: Expr, right: Expr, wrapper: &mut Wrapper, state:
                 }
                 _ => {
                     eval(vec![left.clone()], wrapper, state);
+                    wrapper.push(mov!(ident!("temp"), ident!("temp2".to_string())));
                     ident!("temp2")
                 }
             }
@@ -172,12 +1657,6 @@ fn gen_cmp(op: Operator, left: Expr, right: Expr, wrapper: &mut Wrapper, state:
             wrapper.push(mov!(ident!("temp"), ident!("temp2".to_string())));
             ident!("temp2")
         }
-        Expr::String(_) => {
-            println!("[Engine] WARN! Left-side string comparison is not supported.");
-            eval(vec![left.clone()], wrapper, state);
-            wrapper.push(mov!(ident!("temp"), ident!("temp2".to_string())));
-            ident!("temp2")
-        }
         _ => {
             eval(vec![left.clone()], wrapper, state);
             wrapper.push(mov!(ident!("temp"), ident!("temp2".to_string())));
@@ -187,7 +1666,29 @@ fn gen_cmp(op: Operator, left: Expr, right: Expr, wrapper: &mut Wrapper, state:
 
     let right_macro = match right {
         Expr::Number(n) => immediate!(SIGNED(n)),
+        Expr::Char(c) => immediate!(SIGNED(c as i64)),
+        // Names in the state are functions, see `Expr::CallFunc`.
+        Expr::Identifier(ref i) if state.contains_key(i) => {
+            gen_function_value(i, None, wrapper, state);
+            ident!("temp")
+        }
         Expr::Identifier(ref i) => ident!(i),
+        Expr::DecFunc(ref name, _, _, _) => {
+            eval(vec![right.clone()], wrapper, state);
+            gen_function_value(name, None, wrapper, state);
+            ident!("temp")
+        }
+        Expr::Closure(ref func, ref captures) => {
+            gen_closure(func, captures, wrapper, state);
+            ident!("temp")
+        }
+        Expr::PropertyAccess(ref obj, _) if is_array(obj, state) || is_map(obj, state) || is_tuple(obj, state) => {
+            eval(vec![right.clone()], wrapper, state);
+            match is_string(&right, state) {
+                true => ident!("temp_struct"),
+                false => ident!("temp")
+            }
+        }
         Expr::PropertyAccess(ref obj, ref prop) => {
             // NOTE: This means that every time a property is a call (such as `string.new()`), it
             // will attempt to store the result in a struct. This is because I am bad at rust.
@@ -218,12 +1719,13 @@ fn gen_cmp(op: Operator, left: Expr, right: Expr, wrapper: &mut Wrapper, state:
                         "struct" => {
                             ident!("temp_struct")
                         }
-                        "callback" => {
-                            panic!()
-                        }
                         "null" => {
                             panic!()
                         }
+                        // Arrays are pointers to their header, like `env.args()` returns.
+                        t if t.starts_with("array") => {
+                            ident!("temp")
+                        }
                         _ => {
                             println!("[Engine] WARN! Assuming I64 on function return type.");
                             ident!("temp")
@@ -241,6 +1743,14 @@ fn gen_cmp(op: Operator, left: Expr, right: Expr, wrapper: &mut Wrapper, state:
             eval(vec![right.clone()], wrapper, state);
             ident!("temp")
         }
+        Expr::Cast(_, ref typ) => {
+            eval(vec![right.clone()], wrapper, state);
+            ident!(cast_temp_name(typ))
+        }
+        Expr::BinOp(_, _, _) | Expr::Interpolation(_) | Expr::ArrayAccess(_, _, _) if is_string(&right, state) => {
+            eval(vec![right.clone()], wrapper, state);
+            ident!("temp_struct")
+        }
         Expr::String(ref s) => {
             create_rainbow_string(s.to_string(), wrapper);
             wrapper.push(pop!(ident!("temp_struct")));
@@ -271,6 +1781,8 @@ fn gen_cmp(op: Operator, left: Expr, right: Expr, wrapper: &mut Wrapper, state:
 pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, String>) {
     let mut i = 0;
     while i < ast.len() {
+        // Calls to generic functions go to the instance for their arguments.
+        let resolved = resolve_call(&ast[i], state);
         match &ast[i] {
             Expr::BinOp(op, left, right) => {
                 let bytes = gen_cmp(op.clone(), *left.clone(), *right.clone(), wrapper, state);
@@ -278,46 +1790,35 @@ pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, S
             },
 
             Expr::Array(items) => {
-                let bytes = alloc!(
-                    rbtype!(I64),
-                    immediate!(SIGNED(items.len() as i64)),
-                    ident!("temp3")
-                );
-                wrapper.push(bytes);
+                // Leave some room to grow, so that the first few pushes don't reallocate.
+                let capacity = std::cmp::max(items.len(), 4);
+                wrapper.push(push!(immediate!(SIGNED(capacity as i64))));
+                wrapper.push(call!(name!("myst_array_new")));
+                wrapper.push(pop!(ident!("temp3")));
+                wrapper.push(pmov!(immediate!(SIGNED(items.len() as i64)), ident!("temp3"), immediate!(SIGNED(0))));
 
                 let mut i = 0;
                 while i < items.len() {
-                    match &items[i] {
-                        Expr::Number(n) => {
-                            let bytes = pmov!(
-                                immediate!(SIGNED(*n)),
-                                ident!("temp3"),
-                                immediate!(SIGNED(i as i64))
-                            );
-
-                            wrapper.push(bytes);
-                        },
-                        Expr::Identifier(name) => {
-                            let bytes = pmov!(
-                                ident!(name.clone()),
-                                ident!("temp3"),
-                                immediate!(SIGNED(i as i64))
-                            );
-
-                            wrapper.push(bytes);
-                        },
+                    let value = match &items[i] {
+                        Expr::Number(n) => immediate!(SIGNED(*n)),
+                        Expr::Identifier(name) if !is_string(&items[i], state) => ident!(name.clone()),
                         _ => {
-                            eval(vec![items[i].clone()], wrapper, state);
-                            
-                            let bytes = pmov!(
-                                ident!("temp"),
-                                ident!("temp3"),
-                                immediate!(SIGNED(i as i64))
-                            );
-
-                            wrapper.push(bytes);
+                            // Evaluating the element may build other arrays, which reuses `temp3`.
+                            wrapper.push(push!(ident!("temp3")));
+                            gen_element(&items[i], wrapper, state);
+                            wrapper.push(pop!(ident!("temp3")));
+                            ident!("temp")
                         }
-                    }
+                    };
+
+                    gen_array_data(&"temp3".to_string(), wrapper);
+                    let bytes = pmov!(
+                        value,
+                        ident!("temp_pointer"),
+                        immediate!(SIGNED(i as i64))
+                    );
+
+                    wrapper.push(bytes);
 
                     i += 1;
                 }
@@ -326,11 +1827,130 @@ pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, S
                 wrapper.push(bytes);
             }
 
-            Expr::ArrayAccess(name, index) => {
-                eval(vec![*index.clone()], wrapper, state);
+            Expr::Tuple(items) => {
+                let bytes = alloc!(rbtype!(I64), immediate!(SIGNED(items.len() as i64)), ident!("temp3"));
+                wrapper.push(bytes);
+
+                let mut i = 0;
+                while i < items.len() {
+                    // Evaluating the value may build other tuples or arrays, which reuses `temp3`.
+                    wrapper.push(push!(ident!("temp3")));
+                    gen_element(&items[i], wrapper, state);
+                    wrapper.push(pop!(ident!("temp3")));
+                    wrapper.push(pmov!(ident!("temp"), ident!("temp3"), immediate!(SIGNED(i as i64))));
+
+                    i += 1;
+                }
+
+                wrapper.push(mov!(ident!("temp3"), ident!("temp")));
+            }
+
+            Expr::PropertyAccess(item, prop) if is_tuple(item, state) => {
+                let index = match **prop {
+                    Expr::Number(n) => n,
+                    _ => panic!("Tuples only have numbered values, got {:?}", prop)
+                };
+
+                eval(vec![*item.clone()], wrapper, state);
+                wrapper.push(add!(ident!("temp"), immediate!(SIGNED(index)), ident!("temp")));
+                wrapper.push(self::deref!(ident!("temp"), ident!("temp")));
+
+                if is_string(&ast[i], state) {
+                    gen_unbox_string(wrapper);
+                }
+            }
+
+            Expr::Map(entries) => {
+                let kind = match entries.first() {
+                    Some((key, _)) => value_kind(key, state),
+                    None => "number".to_string()
+                };
+
+                wrapper.push(call!(name!("myst_map_new")));
+
+                // `myst_map_set` hands the map back, so it never has to leave the stack while the
+                // entries, which may contain other maps, are evaluated.
+                for (key, value) in entries.iter() {
+                    gen_element(key, wrapper, state);
+                    wrapper.push(push!(ident!("temp")));
+                    gen_element(value, wrapper, state);
+                    wrapper.push(push!(ident!("temp")));
+                    wrapper.push(call!(name!(format!("myst_map_set_{}", kind))));
+                }
+
+                wrapper.push(pop!(ident!("temp")));
+            }
+
+            Expr::ArrayAccess(target, key, line) if is_map(target, state) => {
+                let kind = key_kind(target, state);
+
+                eval(vec![*target.clone()], wrapper, state);
+                wrapper.push(push!(ident!("temp")));
+                wrapper.push(push!(ident!("temp")));
+                gen_element(key, wrapper, state);
+                wrapper.push(push!(ident!("temp")));
+                wrapper.push(call!(name!(format!("myst_map_find_{}", kind))));
+                wrapper.push(pop!(ident!("temp")));
+                wrapper.push(pop!(ident!("temp_array")));
+
+                let file = get_option(state, "file").unwrap_or("<unknown>".to_string());
+                let message = Expr::String(format!("{}:{}: Key not found in `{}`", file, line, target.describe()));
+                let fail_scope = gen_abort(message, wrapper, state);
+                wrapper.push(generate_scope(&[
+                    cmp!(cond!(>=), ident!("temp"), immediate!(SIGNED(0)), ident!("temp_check")),
+                    jne!(ident!("temp_check"), immediate!(SIGNED(1)), immediate!(SIGNED(3))),
+                    jmp!(immediate!(SIGNED(4))), // Found, skip the failure scope
+                    fail_scope,
+                ].concat()));
+
+                wrapper.push(add!(ident!("temp_array"), immediate!(SIGNED(1)), ident!("temp_pointer")));
+                wrapper.push(self::deref!(ident!("temp_pointer"), ident!("temp_pointer")));
+                gen_array_data(&"temp_pointer".to_string(), wrapper);
+                wrapper.push(add!(ident!("temp_pointer"), ident!("temp"), ident!("temp")));
+                wrapper.push(self::deref!(ident!("temp"), ident!("temp")));
+
+                if is_string(&ast[i], state) {
+                    gen_unbox_string(wrapper);
+                }
+            }
+
+            Expr::ArrayAccess(target, index, line) if is_string(target, state) => {
+                // Strings are `{ text, length }` structs, indexing reads a single byte of `text`.
+                let (text, length) = match &**target {
+                    Expr::Identifier(name) => {
+                        eval(vec![*index.clone()], wrapper, state);
+                        (format!("{}.text", name), format!("{}.length", name))
+                    }
+                    _ => {
+                        eval(vec![*target.clone()], wrapper, state);
+                        wrapper.push(push!(ident!("temp_struct.text")));
+                        wrapper.push(push!(ident!("temp_struct.length")));
+                        eval(vec![*index.clone()], wrapper, state);
+                        wrapper.push(pop!(ident!("temp_pointer")));
+                        wrapper.push(pop!(ident!("temp_array")));
+                        ("temp_array".to_string(), "temp_pointer".to_string())
+                    }
+                };
+
+                gen_bounds_check(&target.describe(), *line, ident!(length), wrapper, state);
+
+                let pointer = add!(
+                    ident!(text),
+                    ident!("temp"),
+                    ident!("temp")
+                );
+                wrapper.push(pointer);
+
+                let bytes = self::deref!(ident!("temp"), ident!("temp".to_string()));
+                wrapper.push(bytes);
+            }
+
+            Expr::ArrayAccess(target, index, line) => {
+                let name = gen_array_index(target, index, *line, wrapper, state);
 
+                gen_array_data(&name, wrapper);
                 let pointer = add!(
-                    ident!(name),
+                    ident!("temp_pointer"),
                     ident!("temp"),
                     ident!("temp")
                 );
@@ -338,6 +1958,34 @@ pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, S
 
                 let bytes = self::deref!(ident!("temp"), ident!("temp".to_string()));
                 wrapper.push(bytes);
+
+                if is_string(&ast[i], state) {
+                    gen_unbox_string(wrapper);
+                }
+            }
+
+            Expr::Cast(value, typ) => {
+                let source = match *value.clone() {
+                    Expr::Number(n) => immediate!(SIGNED(n)),
+                    Expr::Identifier(name) => ident!(name),
+                    _ => {
+                        eval(vec![*value.clone()], wrapper, state);
+                        ident!("temp")
+                    }
+                };
+
+                // Rainbow converts between numeric types when moving into a variable of a
+                // different type, so a cast is a move into a scratch variable of the target type,
+                // declared in `gen_preamble`.
+                let target = cast_temp_name(typ);
+                wrapper.push(mov!(source, ident!(target.clone())));
+
+                // Leave a copy in `temp` for code that expects every result there.
+                wrapper.push(mov!(ident!(target), ident!("temp")));
+            }
+
+            Expr::DecFunc(_, _, _, _) if ast[i].type_params().len() > 0 => {
+                gen_instances(&ast[i], wrapper, state);
             }
 
             Expr::DecFunc(name, args, body, typ) => {
@@ -345,53 +1993,26 @@ pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, S
                 let mut i = 0;
                 while i < args.len() {
                     match &args[i] {
-                        Expr::Parameter(name, typ) => {
+                        Expr::Parameter(name, typ, _) | Expr::RestParameter(name, typ) => {
                             let t = match typ {
                                 MType::Number => Type::I64,
-                                MType::String => Type::STRUCT("_".to_string()),
+                                MType::String => {
+                                    set_var_type(state, name, "string");
+                                    Type::STRUCT("_".to_string())
+                                },
                                 MType::Struct => Type::STRUCT("_".to_string()),
-                                MType::Function => {
-                                    state.insert(name.clone(), "callback".to_string());
-                                    Type::NAME
+                                // Function values are pointers, see `gen_function_value`.
+                                t if function_kind(t).is_some() => {
+                                    set_var_type(state, name, &function_kind(t).unwrap());
+                                    Type::I64
                                 },
-                                MType::Nested(parent, child) => {
-                                    let parent = match *parent.clone() {
-                                        MType::Number => Type::I64,
-                                        MType::String => Type::STRUCT("_".to_string()),
-                                        MType::Struct => Type::STRUCT("_".to_string()),
-                                        MType::Function => {
-                                            state.insert(name.clone(), "callback".to_string());
-                                            Type::NAME
-                                        },
-                                        _ => Type::I64
-                                    };
-
-                                    let child = match *child.clone() {
-                                        MType::Number => {
-                                            state.insert(name.clone(), "callback-number".to_string());
-                                            Type::I64
-                                        }
-                                        MType::String => {
-                                            state.insert(name.clone(), "callback-string".to_string());
-                                            Type::STRUCT("_".to_string())
-                                        },
-                                        MType::Struct => {
-                                            state.insert(name.clone(), "callback-struct".to_string());
-                                            Type::STRUCT("_".to_string())
-                                        },
-                                        MType::Function => {
-                                            state.insert(name.clone(), "callback-callback".to_string());
-                                            Type::NAME
-                                        },
-                                        _ => {
-                                            state.insert(name.clone(), "callback-number".to_string());
-                                            Type::I64
-                                        }
-                                    };
-
-                                    child
+                                t if is_pointer_type(t) => {
+                                    set_var_type(state, name, &type_kind(t));
+                                    Type::I64
                                 }
                                 MType::Undefined => Type::VOID,
+                                MType::Char => Type::U8,
+                                t if t.is_numeric() => numeric_type(t),
                                 _ => Type::I64
                             };
                             rb_args.push(Arg {
@@ -407,6 +2028,24 @@ pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, S
                     i += 1;
                 }
 
+                // Calls pass everything after the fixed parameters as one array.
+                if let Some(Expr::RestParameter(_, _)) = args.last() {
+                    state.insert(format!("variadic {}", name), (args.len() - 1).to_string());
+                }
+
+                // Functions returning heap values hand back a pointer, just like a number, except
+                // for tuples, whose values are returned separately. They are known before their
+                // body is compiled, so that they can call themselves.
+                if let MType::Tuple(_) = typ {
+                    state.insert(name.clone(), type_kind(typ));
+                    state.insert(format!("returns {}", name), type_kind(typ));
+                } else if is_pointer_type(typ) {
+                    state.insert(name.clone(), "number".to_string());
+                    state.insert(format!("returns {}", name), type_kind(typ));
+                } else {
+                    state.insert(name.clone(), typ.stringify());
+                }
+
                 let mut func_wrapper = Wrapper::new();
                 let create_var_bytes = var!(
                     Value::TYPE(vec![Type::I64]),
@@ -414,92 +2053,48 @@ pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, S
                 );
                 func_wrapper.push(create_var_bytes);
 
+                // `return` needs to know whether it returns several values, and for loops which
+                // variables they can use again.
+                let enclosing = state.insert("returning".to_string(), type_kind(typ));
+                let enclosing_function = state.insert("in function".to_string(), name.clone());
                 eval(*body.clone(), &mut func_wrapper, state);
+                match enclosing {
+                    Some(kind) => state.insert("returning".to_string(), kind),
+                    None => state.remove("returning")
+                };
+                match enclosing_function {
+                    Some(name) => state.insert("in function".to_string(), name),
+                    None => state.remove("in function")
+                };
                 let function_bytes = func_wrapper.bytes.clone();
 
                 wrapper.merge_data(&func_wrapper); // Note: we do this because strings are not
                 // stored in `bytes`, but are a seperate thing. @gromton12 please fix this.
                
-                state.insert(name.clone(), typ.stringify());
                 
-                let bytes = generate_function(name, &rb_args, &vec![Type::I64], &function_bytes);
+                let return_types = match typ {
+                    // Strings in a tuple are returned as they are, see `gen_return_values`.
+                    MType::Tuple(types) => types.iter().map(|t| match t {
+                        MType::String => Type::STRUCT("_".to_string()),
+                        t => return_type(t)
+                    }).collect(),
+                    t => vec![return_type(t)]
+                };
+                let bytes = generate_function(name, &rb_args, &return_types, &function_bytes);
 
                 wrapper.push(bytes);
             }
 
+            Expr::CallFunc(_, _) if resolved.is_some() => {
+                eval(vec![resolved.unwrap()], wrapper, state);
+            }
+
             Expr::CallFunc(name, args) => {
                 let name = match *name.clone() {
                     Expr::Identifier(name) => name.clone(),
                     _ => panic!("Expected identifier, got {:?}", name)
                 };
-
-                // Evaluate the arguments
-                let mut i = 0;
-                while i < args.len() {
-                    match &args[i] {
-                        Expr::Number(n) => {
-                            wrapper.push(push!(immediate!(SIGNED(*n))));
-                        }
-                        Expr::Identifier(name) => {
-                            // Explanation for later me:
-                            // If the `name` exists in our cope-state, it is a function.
-                            // If it does not exist, it is a variable.
-                            // Probably.
-                            // TODO: Rework how the engine stores state.
-                            match state.get(name) {
-                                Some(_t) => {
-                                    wrapper.push(push!(immediate!(NAME(name.clone()))));
-                                }
-                                None => {
-                                    wrapper.push(push!(ident!(name.clone())));
-                                }
-                            };
-                        }
-                        Expr::String(s) => {
-                            create_rainbow_string(s.to_string(), wrapper);
-                            wrapper.push(pop!(ident!("temp_struct")));
-                        }
-                        Expr::BinOp(_, _, _) => {
-                            eval(vec![args[i].clone()], wrapper, state);
-                            let bytes = push!(ident!("temp"));
-                            wrapper.push(bytes);
-                        }
-                        Expr::DecFunc(name, _, _, _typ) => {
-                            eval(vec![args[i].clone()], wrapper, state);
-                            let bytes = push!(name!(name.clone()));
-                            wrapper.push(bytes);
-                        }
-                        _ => {
-                            // arg is stored in `temp`
-                            eval(vec![args[i].clone()], wrapper, state);
-                            let bytes = push!(ident!("temp"));
-                            wrapper.push(bytes);
-                        }
-                    }
-
-                    i += 1;
-                }
-
-                let is_pointer = match state.get(&name) {
-                    Some(t) => t.starts_with("callback"),
-                    None => false
-                };
-
-                match is_pointer {
-                    true => {
-                        let bytes = call!(ident!(name));
-                        wrapper.push(bytes);
-                    }
-                    false => {
-                        let bytes = call!(name!(name));
-                        wrapper.push(bytes);
-                    }
-                }
-
-                let typ = match state.get(&name) {
-                    Some(t) => t,
-                    None => &MType::Undefined.stringify()
-                };
+                let typ = gen_call(&name, args, wrapper, state);
 
                 let bytes = match typ.as_str() {
                     "number" => {
@@ -511,10 +2106,12 @@ pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, S
                     "struct" => {
                         pop!(ident!("temp_struct"))
                     }
-                    "callback" => {
+                    "null" => {
                         nop!()
                     }
-                    "null" => {
+                    // Several values are put back together into a tuple.
+                    kind if tuple_kinds(kind).is_some() => {
+                        gen_collect_tuple(&tuple_kinds(kind).unwrap(), wrapper);
                         nop!()
                     }
                     _ => {
@@ -531,6 +2128,11 @@ pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, S
                 wrapper.push(bytes);
             }
 
+            Expr::Char(c) => {
+                let bytes = mov!(immediate!(SIGNED(*c as i64)), ident!("temp"));
+                wrapper.push(bytes);
+            }
+
             Expr::String(s) => {
                 wrapper.push_string(&s);
                 wrapper.push(push!(ident!(Wrapper::get_string_name(s))));
@@ -542,10 +2144,37 @@ pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, S
                 wrapper.push(bytes);
             }
 
+            Expr::Interpolation(parts) => {
+                // Every part is pushed as a string, and folded together with `string.concat`,
+                // which leaves its result on the stack for the next part.
+                let mut j = 0;
+                while j < parts.len() {
+                    if is_string(&parts[j], state) {
+                        push_string_operand(&parts[j], wrapper, state);
+                    } else {
+                        eval(vec![parts[j].clone()], wrapper, state);
+                        wrapper.push(push!(ident!("temp")));
+                        wrapper.push(call!(name!("string.ntos")));
+                    }
+
+                    if j > 0 {
+                        wrapper.push(call!(name!("string.concat")));
+                    }
+
+                    j += 1;
+                }
+
+                wrapper.push(pop!(ident!("temp_struct")));
+            }
+
             Expr::Import(name) => {
                 wrapper.push_import(&format!("{}", name));
             }
 
+            Expr::Include(file) => {
+                set_option(state, "file", file);
+            }
+
             Expr::If(cond, body, else_body) => {
                 eval(vec![*cond.clone()], wrapper, state);
                 // `temp` is the condition
@@ -609,6 +2238,10 @@ pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, S
                 wrapper.push(wrap);
             }
 
+            Expr::Return(val) if state.get("returning").is_some_and(|kind| tuple_kinds(kind).is_some()) => {
+                gen_return_values(val, wrapper, state);
+            }
+
             Expr::Return(val) => {
                 eval(vec![*val.clone()], wrapper, state);
 
@@ -624,6 +2257,107 @@ pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, S
                 wrapper.push(nop!());
             }
 
+            Expr::PropertyAccess(item, prop) if is_map(item, state) => {
+                let kind = key_kind(item, state);
+                eval(vec![*item.clone()], wrapper, state);
+
+                match *prop.clone() {
+                    Expr::Identifier(name) if name == "length" => {
+                        wrapper.push(self::deref!(ident!("temp"), ident!("temp")));
+                        wrapper.push(self::deref!(ident!("temp"), ident!("temp")));
+                    }
+                    Expr::Identifier(name) if name == "keys" => {
+                        wrapper.push(self::deref!(ident!("temp"), ident!("temp")));
+                    }
+                    Expr::Identifier(name) if name == "values" => {
+                        wrapper.push(add!(ident!("temp"), immediate!(SIGNED(1)), ident!("temp")));
+                        wrapper.push(self::deref!(ident!("temp"), ident!("temp")));
+                    }
+                    Expr::CallFunc(name, args) => {
+                        let name = match *name.clone() {
+                            Expr::Identifier(name) => name.clone(),
+                            _ => panic!("Expected identifier, got {:?}", name)
+                        };
+
+                        wrapper.push(push!(ident!("temp")));
+                        match (name.as_str(), args.len()) {
+                            ("has", 1) => {
+                                gen_element(&args[0], wrapper, state);
+                                wrapper.push(push!(ident!("temp")));
+                                wrapper.push(call!(name!(format!("myst_map_find_{}", kind))));
+                                wrapper.push(pop!(ident!("temp")));
+                                wrapper.push(cmp!(cond!(>=), ident!("temp"), immediate!(SIGNED(0)), ident!("temp")));
+                            }
+                            ("remove", 1) => {
+                                gen_element(&args[0], wrapper, state);
+                                wrapper.push(push!(ident!("temp")));
+                                wrapper.push(call!(name!(format!("myst_map_remove_{}", kind))));
+                                wrapper.push(pop!(ident!("temp")));
+                            }
+                            _ => panic!("Maps have no method `{}` taking {} arguments", name, args.len())
+                        }
+                    }
+                    _ => panic!("Maps have no property {:?}", prop)
+                }
+            }
+
+            Expr::For(names, iterable, body, line) => {
+                gen_for(names, iterable, body, *line, wrapper, state);
+            }
+
+            Expr::Closure(func, captures) => {
+                gen_closure(func, captures, wrapper, state);
+            }
+
+            Expr::PropertyAccess(item, prop) if is_array(item, state) => {
+                // The array is the first argument of the runtime functions, so it can go straight
+                // on the stack before the other arguments are evaluated.
+                eval(vec![*item.clone()], wrapper, state);
+
+                match *prop.clone() {
+                    Expr::Identifier(name) if name == "length" => {
+                        wrapper.push(self::deref!(ident!("temp"), ident!("temp")));
+                    }
+                    Expr::CallFunc(name, args) => {
+                        let name = match *name.clone() {
+                            Expr::Identifier(name) => name.clone(),
+                            _ => panic!("Expected identifier, got {:?}", name)
+                        };
+
+                        match (name.as_str(), args.len()) {
+                            ("push", 1) => {
+                                wrapper.push(push!(ident!("temp")));
+                                gen_array_element(&args[0], &array_element_kind(item, state), wrapper, state);
+                                wrapper.push(push!(ident!("temp")));
+                                wrapper.push(call!(name!("myst_array_push")));
+                            }
+                            ("pop", 0) => {
+                                wrapper.push(push!(ident!("temp")));
+                                wrapper.push(call!(name!("myst_array_pop")));
+                            }
+                            _ => panic!("Arrays have no method `{}` taking {} arguments", name, args.len())
+                        }
+
+                        wrapper.push(pop!(ident!("temp")));
+                        if is_string(&ast[i], state) {
+                            gen_unbox_string(wrapper);
+                        }
+                    }
+                    _ => panic!("Arrays have no property {:?}", prop)
+                }
+            }
+
+            Expr::PropertyAccess(item, prop) if is_string(item, state) && !matches!(**item, Expr::Identifier(_)) => {
+                eval(vec![*item.clone()], wrapper, state);
+
+                match *prop.clone() {
+                    Expr::Identifier(name) => {
+                        wrapper.push(mov!(ident!(format!("temp_struct.{}", name)), ident!("temp")));
+                    }
+                    _ => panic!("Strings have no property {:?}", prop)
+                }
+            }
+
             // TODO: This is horribly nested. Rewrite this so it is recursive instead.
             Expr::PropertyAccess(item, prop) => {
                 let item = match *item.clone() {
@@ -680,6 +2414,18 @@ pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, S
                                         }
                                     }
                                 }
+                                Expr::BinOp(_, _, _) | Expr::Interpolation(_) | Expr::ArrayAccess(_, _, _) if is_string(&args[j], state) => {
+                                    eval(vec![args[j].clone()], wrapper, state);
+                                    match full_name.as_str() {
+                                        "io.println" | "io.print" => {
+                                            wrapper.push(push!(ident!("temp_struct.text")));
+                                            wrapper.push(push!(ident!("temp_struct.length")));
+                                        }
+                                        _ => {
+                                            wrapper.push(push!(ident!("temp_struct")));
+                                        }
+                                    }
+                                }
                                 Expr::PropertyAccess(obj, prop) => {
                                     let obj = match *obj.clone() {
                                         Expr::Identifier(name) => name.clone(),
@@ -687,7 +2433,7 @@ pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, S
                                     };
                                     let prop = match *prop.clone() {
                                         Expr::Identifier(name) => name.clone(),
-                                        Expr::ArrayAccess(_, _) => {
+                                        Expr::ArrayAccess(_, _, _) => {
                                             eval(vec![args[j].clone()], wrapper, state);
                                             let bytes = push!(ident!("temp"));
                                             wrapper.push(bytes);
@@ -735,7 +2481,7 @@ pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, S
                             "struct" => {
                                 wrapper.push(pop!(ident!("temp_struct")))
                             }
-                            "callback" | "null" | "undefined" => {
+                            "null" | "undefined" => {
                             }
                             _ => {
                                 // Assume number on function return types
@@ -744,7 +2490,11 @@ pub fn eval(ast: Vec<Expr>, wrapper: &mut Wrapper, state: &mut HashMap<String, S
                         };
 
                     }
-                    Expr::ArrayAccess(name, index) => {
+                    Expr::ArrayAccess(name, index, _) => {
+                        let name = match *name.clone() {
+                            Expr::Identifier(name) => name.clone(),
+                            _ => panic!("Expected identifier, got {:?}", name)
+                        };
                         let index = match *index.clone() {
                             Expr::Number(n) => n,
                             _ => panic!("Expected number, got {:?}", index)
@@ -1,4 +1,6 @@
-#[derive(Debug, Clone)]
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum MType {
     Number, // i64
     I64, I32, I16, I8,
@@ -6,20 +8,45 @@ pub enum MType {
     F64, F32, F16,
 
     String,
+    Char,
+    Array,
     Function,
     Class,
     Struct,
     Null, Undefined,
 
-    Nested(Box<MType>, Box<MType>)
+    Nested(Box<MType>, Box<MType>),
+    Map(Box<MType>, Box<MType>), // Map<Key, Value>
+    Tuple(Vec<MType>),
+    Fn(Vec<MType>, Box<MType>), // Fn(Params) -> Return
+    Generic(String), // A type parameter, such as `T` in `fn first<T>(a: Array<T>): T`
+    Closure(Box<MType>) // A function that carries the variables it captured, called like its `Fn`
 }
 
 impl MType {
     pub fn stringify(&self) -> String {
         match self {
             MType::Number => "number".to_string(),
+            MType::I64 => "i64".to_string(),
+            MType::I32 => "i32".to_string(),
+            MType::I16 => "i16".to_string(),
+            MType::I8 => "i8".to_string(),
+            MType::U64 => "u64".to_string(),
+            MType::U32 => "u32".to_string(),
+            MType::U16 => "u16".to_string(),
+            MType::U8 => "u8".to_string(),
+            MType::F64 => "f64".to_string(),
+            MType::F32 => "f32".to_string(),
+            MType::F16 => "f16".to_string(),
             MType::String => "string".to_string(),
+            MType::Char => "char".to_string(),
+            MType::Array => "array".to_string(),
+            MType::Map(_, _) => "map".to_string(),
+            MType::Tuple(_) => "tuple".to_string(),
             MType::Function => "function".to_string(),
+            MType::Fn(_, _) => "function".to_string(),
+            MType::Closure(_) => "closure".to_string(),
+            MType::Generic(name) => name.clone(),
             MType::Class => "class".to_string(),
             MType::Struct => "struct".to_string(),
             MType::Null => "null".to_string(),
@@ -29,6 +56,137 @@ impl MType {
             }
         }
     }
+
+    /// Returns true for `Number` and every sized integer or float type.
+    pub fn is_numeric(&self) -> bool {
+        match self {
+            MType::Number
+            | MType::I64 | MType::I32 | MType::I16 | MType::I8
+            | MType::U64 | MType::U32 | MType::U16 | MType::U8
+            | MType::F64 | MType::F32 | MType::F16 => true,
+            _ => false
+        }
+    }
+
+    /// Returns true for the float types.
+    pub fn is_float(&self) -> bool {
+        match self {
+            MType::F64 | MType::F32 | MType::F16 => true,
+            _ => false
+        }
+    }
+
+    /// Returns true for the signed integer types, including `Number`.
+    pub fn is_signed(&self) -> bool {
+        match self {
+            MType::Number | MType::I64 | MType::I32 | MType::I16 | MType::I8 => true,
+            _ => false
+        }
+    }
+
+    /// Size of a numeric type in bits. `Number` is an alias for `I64`.
+    pub fn bits(&self) -> u32 {
+        match self {
+            MType::Number | MType::I64 | MType::U64 | MType::F64 => 64,
+            MType::I32 | MType::U32 | MType::F32 => 32,
+            MType::I16 | MType::U16 | MType::F16 => 16,
+            MType::I8 | MType::U8 => 8,
+            _ => 0
+        }
+    }
+
+    /// Whether a value of this type can be implicitly converted into `target` without losing
+    /// information.
+    ///
+    /// Integers widen within their signedness, unsigned integers widen into any strictly larger
+    /// signed integer, and integers widen into a float that has room for their full range.
+    /// Everything else (narrowing, signed to unsigned, float to integer) requires an `as` cast.
+    pub fn widens_to(&self, target: &MType) -> bool {
+        if !self.is_numeric() || !target.is_numeric() {
+            return false;
+        }
+
+        if self.bits() == target.bits() && self.is_float() == target.is_float() && self.is_signed() == target.is_signed() {
+            return true;
+        }
+
+        match (self.is_float(), target.is_float()) {
+            (true, true) => self.bits() <= target.bits(),
+            (true, false) => false,
+            (false, true) => self.bits() < target.bits(),
+            (false, false) => {
+                if self.is_signed() == target.is_signed() {
+                    self.bits() <= target.bits()
+                } else if !self.is_signed() {
+                    self.bits() < target.bits()
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    /// Adds the names of the type parameters used in this type to `names`, in the order they
+    /// first appear.
+    pub fn type_params(&self, names: &mut Vec<String>) {
+        match self {
+            MType::Generic(name) => {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+            MType::Nested(parent, child) | MType::Map(parent, child) => {
+                parent.type_params(names);
+                child.type_params(names);
+            }
+            MType::Tuple(types) => types.iter().for_each(|t| t.type_params(names)),
+            MType::Fn(params, returns) => {
+                params.iter().for_each(|t| t.type_params(names));
+                returns.type_params(names);
+            }
+            MType::Closure(function_type) => function_type.type_params(names),
+            _ => {}
+        }
+    }
+
+    /// Whether this type uses any type parameters.
+    pub fn is_generic(&self) -> bool {
+        let mut names = vec![];
+        self.type_params(&mut names);
+        names.len() > 0
+    }
+
+    /// Replaces the type parameters in this type with the types they are bound to.
+    pub fn substitute(&self, bindings: &HashMap<String, MType>) -> MType {
+        match self {
+            MType::Generic(name) => bindings.get(name).cloned().unwrap_or(self.clone()),
+            MType::Nested(parent, child) => MType::Nested(Box::new(parent.substitute(bindings)), Box::new(child.substitute(bindings))),
+            MType::Map(key, value) => MType::Map(Box::new(key.substitute(bindings)), Box::new(value.substitute(bindings))),
+            MType::Tuple(types) => MType::Tuple(types.iter().map(|t| t.substitute(bindings)).collect()),
+            MType::Fn(params, returns) => MType::Fn(
+                params.iter().map(|t| t.substitute(bindings)).collect(),
+                Box::new(returns.substitute(bindings))
+            ),
+            MType::Closure(function_type) => MType::Closure(Box::new(function_type.substitute(bindings))),
+            _ => self.clone()
+        }
+    }
+
+    /// Whether the integer literal `n` fits in this type without a cast.
+    pub fn fits_literal(&self, n: i64) -> bool {
+        match self {
+            MType::Number | MType::I64 => true,
+            MType::I32 => n >= i32::MIN as i64 && n <= i32::MAX as i64,
+            MType::I16 => n >= i16::MIN as i64 && n <= i16::MAX as i64,
+            MType::I8 => n >= i8::MIN as i64 && n <= i8::MAX as i64,
+            MType::U64 => n >= 0,
+            MType::U32 => n >= 0 && n <= u32::MAX as i64,
+            MType::U16 => n >= 0 && n <= u16::MAX as i64,
+            MType::U8 => n >= 0 && n <= u8::MAX as i64,
+            MType::F64 | MType::F32 | MType::F16 => true,
+            _ => false
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -37,31 +195,132 @@ pub enum Expr {
 
     Number(i64),
     String(String),
+    Char(char),
+    Interpolation(Box<Vec<Expr>>),
     Array(Box<Vec<Expr>>),
+    Map(Box<Vec<(Expr, Expr)>>),
+    Tuple(Box<Vec<Expr>>),
     Identifier(String),
 
-    ArrayAccess(String, Box<Expr>),
+    ArrayAccess(Box<Expr>, Box<Expr>, usize), // The line it is on, for bounds check errors
+    Cast(Box<Expr>, MType),
     PropertyAccess(Box<Expr>, Box<Expr>),
     
     If(Box<Expr>, Box<Vec<Expr>>, Box<Vec<Expr>>),
     While(Box<Expr>, Box<Vec<Expr>>),
-    For(String, Box<Expr>, Box<Vec<Expr>>),
+    For(Vec<String>, Box<Expr>, Box<Vec<Expr>>, usize), // The line it is on, for bounds check errors
 
-    Parameter(String, MType),
+    Parameter(String, MType, Option<Box<Expr>>), // The default value, if it has one
+    RestParameter(String, MType), // `...parts: Array<String>`, always the last parameter
     DecFunc(String, Vec<Expr>, Box<Vec<Expr>>, MType),
+    Closure(Box<Expr>, Vec<String>), // The anonymous function, and the names it captures
     CallFunc(Box<Expr>, Box<Vec<Expr>>),
+    NamedArg(String, Box<Expr>), // `timeout: 10` in a call, replaced by a positional argument before the engine sees it
+    Spread(Box<Expr>), // `...parts` in a call, passes an array as the rest parameter
 
     DecClass(String, Box<Vec<Expr>>),
     InstantiateClass(String, Box<Vec<Expr>>),
 
     Import(String),
-    Include(String),
+    ImportAs(String, String), // `import "io" as out;`
+    ImportNames(String, Vec<(String, String)>), // `import { println as say } from "io";`, each name with its alias
+    Include(String), // The statements after it come from this file, for the locations in runtime errors
+    Export(Box<Expr>), // A declaration other modules may use, unwrapped when modules are linked
 
     Return(Box<Expr>),
     Pass,
     EOF,
 }
 
+impl Expr {
+    /// The type parameters of a function declaration, in the order they first appear in its
+    /// parameters and return type. Empty for anything that is not a generic function.
+    pub fn type_params(&self) -> Vec<String> {
+        let mut names = vec![];
+        if let Expr::DecFunc(_, params, _, returns) = self {
+            for param in params.iter() {
+                if let Expr::Parameter(_, typ, _) | Expr::RestParameter(_, typ) = param {
+                    typ.type_params(&mut names);
+                }
+            }
+            returns.type_params(&mut names);
+        }
+        names
+    }
+
+    /// A copy of the function declaration `self`, named `name`, with its type parameters
+    /// replaced by the types in `bindings`.
+    pub fn instantiate(&self, name: &String, bindings: &HashMap<String, MType>) -> Expr {
+        match self.substitute(bindings) {
+            Expr::DecFunc(_, params, body, returns) => Expr::DecFunc(name.clone(), params, body, returns),
+            _ => panic!("Cannot instantiate {:?}, expected a function", self)
+        }
+    }
+
+    /// Replaces the type parameters in every type written inside `self`.
+    fn substitute(&self, bindings: &HashMap<String, MType>) -> Expr {
+        self.transform(&|expr| match expr {
+            Expr::BinOp(Operator::Declare(typ), left, right) => {
+                Expr::BinOp(Operator::Declare(typ.substitute(bindings)), left, right)
+            }
+            Expr::Cast(value, typ) => Expr::Cast(value, typ.substitute(bindings)),
+            Expr::Parameter(name, typ, default) => Expr::Parameter(name, typ.substitute(bindings), default),
+            Expr::RestParameter(name, typ) => Expr::RestParameter(name, typ.substitute(bindings)),
+            Expr::DecFunc(name, params, body, returns) => Expr::DecFunc(name, params, body, returns.substitute(bindings)),
+            expr => expr
+        })
+    }
+
+    /// Rebuilds `self` from the bottom up, passing every expression in it through `f` once its
+    /// own sub-expressions have been.
+    pub fn transform(&self, f: &dyn Fn(Expr) -> Expr) -> Expr {
+        f(self.map_children(&mut |expr| expr.transform(f)))
+    }
+
+    /// A copy of `self` with every direct sub-expression replaced by what `f` returns for it.
+    pub fn map_children(&self, f: &mut dyn FnMut(&Expr) -> Expr) -> Expr {
+        fn all(exprs: &Vec<Expr>, f: &mut dyn FnMut(&Expr) -> Expr) -> Box<Vec<Expr>> {
+            Box::new(exprs.iter().map(|expr| f(expr)).collect())
+        }
+
+        match self {
+            Expr::BinOp(op, left, right) => Expr::BinOp(op.clone(), Box::new(f(left)), Box::new(f(right))),
+            Expr::Interpolation(items) => Expr::Interpolation(all(items, f)),
+            Expr::Array(items) => Expr::Array(all(items, f)),
+            Expr::Tuple(items) => Expr::Tuple(all(items, f)),
+            Expr::Map(entries) => Expr::Map(Box::new(entries.iter().map(|(key, value)| (f(key), f(value))).collect())),
+            Expr::ArrayAccess(target, index, line) => Expr::ArrayAccess(Box::new(f(target)), Box::new(f(index)), *line),
+            Expr::Cast(value, typ) => Expr::Cast(Box::new(f(value)), typ.clone()),
+            Expr::PropertyAccess(object, property) => Expr::PropertyAccess(Box::new(f(object)), Box::new(f(property))),
+            Expr::If(cond, body, else_body) => {
+                let cond = Box::new(f(cond));
+                Expr::If(cond, all(body, f), all(else_body, f))
+            }
+            Expr::While(cond, body) => Expr::While(Box::new(f(cond)), all(body, f)),
+            Expr::For(names, iterable, body, line) => Expr::For(names.clone(), Box::new(f(iterable)), all(body, f), *line),
+            Expr::Parameter(name, typ, default) => Expr::Parameter(name.clone(), typ.clone(), default.as_ref().map(|default| Box::new(f(default)))),
+            Expr::DecFunc(name, params, body, returns) => Expr::DecFunc(name.clone(), *all(params, f), all(body, f), returns.clone()),
+            Expr::Closure(func, captures) => Expr::Closure(Box::new(f(func)), captures.clone()),
+            Expr::CallFunc(callee, args) => Expr::CallFunc(Box::new(f(callee)), all(args, f)),
+            Expr::NamedArg(name, value) => Expr::NamedArg(name.clone(), Box::new(f(value))),
+            Expr::Spread(value) => Expr::Spread(Box::new(f(value))),
+            Expr::DecClass(name, body) => Expr::DecClass(name.clone(), all(body, f)),
+            Expr::InstantiateClass(name, args) => Expr::InstantiateClass(name.clone(), all(args, f)),
+            Expr::Return(value) => Expr::Return(Box::new(f(value))),
+            _ => self.clone()
+        }
+    }
+
+    /// A readable name for an indexed value, for error messages.
+    pub fn describe(&self) -> String {
+        match self {
+            Expr::Identifier(name) => name.clone(),
+            Expr::ArrayAccess(target, _, _) => format!("{}[...]", target.describe()),
+            _ => "array".to_string()
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Operator {
     Add, Subtract, Multiply, Divide,
@@ -76,12 +335,13 @@ pub enum Token {
     LParen, RParen,
     LCurly, RCurly,
     LBracket, RBracket,
-    LArrow, RArrow,
+    LArrow, RArrow, FatArrow, ThinArrow,
     Plus, Minus, Star, Slash,
-    Semicolon, Comma, Dot, Colon,
+    Semicolon, Comma, Dot, Ellipsis, Colon,
     Equal, Equality, Not,
 
     String(String),
+    Char(char),
     Number(i64),
     Identifier(String),
 
@@ -95,8 +355,10 @@ pub enum Token {
     Func,
     Import,
     Include,
+    Export,
     Class,
     Return,
+    As,
 
     Pass,
     NewLine,
@@ -115,6 +377,8 @@ impl Token {
             Token::RBracket => "]".to_string(),
             Token::LArrow => "<".to_string(),
             Token::RArrow => ">".to_string(),
+            Token::FatArrow => "=>".to_string(),
+            Token::ThinArrow => "->".to_string(),
             Token::Plus => "+".to_string(),
             Token::Minus => "-".to_string(),
             Token::Star => "*".to_string(),
@@ -122,11 +386,13 @@ impl Token {
             Token::Semicolon => ";".to_string(),
             Token::Comma => ",".to_string(),
             Token::Dot => ".".to_string(),
+            Token::Ellipsis => "...".to_string(),
             Token::Colon => ":".to_string(),
             Token::Equal => "=".to_string(),
             Token::Equality => "==".to_string(),
 
             Token::String(s) => s.to_string(),
+            Token::Char(c) => c.to_string(),
             Token::Number(n) => n.to_string(),
             Token::Identifier(s) => s.to_string(),
 
@@ -0,0 +1,140 @@
+/// Formats Myst source code, the way `myst fmt` does:
+///  - Lines in `{}`, `[]` or `()` are indented 4 spaces more than the line the bracket is
+///    opened on, however many brackets that line opens.
+///  - Trailing whitespace is removed, and so are blank lines at the start and the end.
+///  - Several blank lines in a row become a single one.
+///  - The file ends with a single newline.
+///
+/// Only the whitespace around lines changes, so comments are kept as they are. The lines of
+/// `"""` strings are left alone, as their whitespace is part of the string.
+pub fn format(source: &str) -> String {
+    let mut lines: Vec<String> = vec![];
+    let mut state = State::default();
+    let mut blank = false;
+
+    for line in source.lines() {
+        if state.string.is_some() {
+            lines.push(line.to_string());
+            let level = state.level();
+            state.scan(line, level);
+            continue;
+        }
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            blank = !lines.is_empty();
+            continue;
+        }
+        if blank {
+            lines.push(String::new());
+            blank = false;
+        }
+
+        let in_comment = state.comment;
+        // A line starting by closing brackets lines up with the line they were opened on.
+        let closers = trimmed.chars().take_while(|c| matches!(c, '}' | ']' | ')')).count();
+        let level = match closers {
+            0 => state.level(),
+            _ => state.brackets.len().checked_sub(closers).map_or(0, |i| state.brackets[i] - 1)
+        };
+        let indent = "    ".repeat(level);
+        state.scan(trimmed, level);
+
+        // The whitespace at the end of a line belongs to a `"""` string started on it.
+        let code = match state.string {
+            Some(_) => line.trim_start(),
+            None => trimmed
+        };
+        // The ` * ` lines of `/** */` comments line up under the first `*`.
+        let align = match in_comment && code.starts_with('*') {
+            true => " ",
+            false => ""
+        };
+        lines.push(format!("{}{}{}", indent, align, code));
+    }
+
+    let mut formatted = lines.join("\n");
+    formatted.push('\n');
+    formatted
+}
+
+/// What the formatter is in at the end of the lines scanned so far.
+#[derive(Default)]
+struct State {
+    brackets: Vec<usize>, // The level of the lines in each open bracket, innermost last
+    string: Option<(bool, bool)>, // Whether the string is raw, and whether it is a `"""` string
+    comment: bool // Whether it is in a `/* */` comment
+}
+
+impl State {
+    /// The indentation level of the lines in the innermost bracket.
+    fn level(&self) -> usize {
+        self.brackets.last().copied().unwrap_or(0)
+    }
+
+    /// Scans `line`, which is indented to `level`.
+    fn scan(&mut self, line: &str, level: usize) {
+        let chars: Vec<char> = line.chars().collect();
+        let mut k = 0;
+        while k < chars.len() {
+            let c = chars[k];
+            let next = chars.get(k + 1).copied();
+            k += 1;
+
+            if self.comment {
+                if c == '*' && next == Some('/') {
+                    self.comment = false;
+                    k += 1;
+                }
+                continue;
+            }
+
+            if let Some((raw, triple)) = self.string {
+                match c {
+                    '\\' if !raw => k += 1,
+                    '"' if !triple => self.string = None,
+                    '"' if next == Some('"') && chars.get(k + 1) == Some(&'"') => {
+                        self.string = None;
+                        k += 2;
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            match c {
+                '/' if next == Some('/') => return,
+                '/' if next == Some('*') => {
+                    self.comment = true;
+                    k += 1;
+                }
+                '"' => {
+                    let raw = k >= 2 && chars[k - 2] == 'r';
+                    let triple = next == Some('"') && chars.get(k + 1) == Some(&'"');
+                    if triple {
+                        k += 2;
+                    }
+                    self.string = Some((raw, triple));
+                }
+                '\'' => {
+                    // Skips the character, which may be an escape like `'\x41'`, and the closing `'`.
+                    while k < chars.len() {
+                        k += match chars[k] {
+                            '\\' => 2,
+                            '\'' => {
+                                k += 1;
+                                break;
+                            }
+                            _ => 1
+                        };
+                    }
+                }
+                '{' | '[' | '(' => self.brackets.push(level + 1),
+                '}' | ']' | ')' => {
+                    self.brackets.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+}
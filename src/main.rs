@@ -1,40 +1,75 @@
 use std::env;
 use std::fs;
 use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::collections::HashMap;
+use std::cell::Cell;
 
 mod enums;
 mod tokenizer;
 mod parser;
+mod checker;
 mod engine;
+mod modules;
+mod config;
+mod cli;
+mod formatter;
 
 use crate::enums::Expr;
 
 use rainbow_wrapper::wrapper::Wrapper;
-use rainbow_wrapper::types::*;
-use rainbow_wrapper::var;
 
 use colored::*;
 
-fn get_rb_path() -> String {
-    return env::var("RAINBOW_PATH").unwrap_or_else(|_| "/home/stormy/code/Rainbow/target/debug/rainbow".to_string());
+/// Finds Rainbow from the `--rainbow` and `--lib` flags, the environment, or the `myst.toml` of
+/// the project `path` is in, in that order.
+fn find_rainbow(rainbow_path: Option<String>, libraries: Vec<String>, path: &Path) -> config::Rainbow {
+    let manifest = match config::Manifest::find(path).map(|manifest| config::Manifest::read(&manifest)) {
+        Some(Ok(manifest)) => Some(manifest),
+        Some(Err(e)) => {
+            println!("❌ {}", e);
+            std::process::exit(1);
+        }
+        None => None
+    };
+
+    match config::Rainbow::find(rainbow_path, libraries, manifest.as_ref()) {
+        Ok(rainbow) => rainbow,
+        Err(e) => {
+            println!("❌ {}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
-fn run_with_rb(path: String, debug: bool) -> i32 {
-    let current_dir = String::from(env::current_dir().unwrap().to_str().unwrap());
-    let mut output = Command::new(get_rb_path());
-    output.arg(format!("{}/{}", current_dir, path));
+/// Runs the bytecode at `path` with Rainbow, passing `arguments` on to the program.
+fn run_with_rb(path: String, debug: bool, rainbow: &config::Rainbow, arguments: &Vec<String>) -> i32 {
+    let current_dir = env::current_dir().unwrap();
+    let mut output = Command::new(&rainbow.path);
+    output.arg(current_dir.join(path));
 
-    output.arg("-l");
-    output.arg("/home/stormy/code/Rainbow/core");
+    for library in rainbow.libraries.iter() {
+        output.arg("-l");
+        output.arg(library);
+    }
 
     if debug {
         output.arg("--debug");
     }
 
+    if arguments.len() > 0 {
+        output.arg("--");
+        output.args(arguments);
+    }
+
     let output = match output.output() {
         Ok(o) => o,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            println!("❌ Could not find the Rainbow VM `{}`", rainbow.path);
+            println!("Use `--rainbow <path>`, set RAINBOW_PATH, or add `path` to `[rainbow]` in {}", config::MANIFEST);
+            std::process::exit(1);
+        }
         Err(e) => {
             println!("❌ Could not run Rainbow: {}", e);
             std::process::exit(1);
@@ -51,7 +86,43 @@ fn run_with_rb(path: String, debug: bool) -> i32 {
     return output.status.code().unwrap();
 }
 
-fn run_tests(debug: bool) {
+/// Return types of the functions in the implicitly imported core libraries.
+fn stdlib_signatures() -> HashMap<String, String> {
+    // Poor man's function signatures. @gromton12 kindly fix this.
+    let mut state: HashMap<String, String> = HashMap::new();
+    state.insert("string.ntos".to_string(), "string".to_string());
+    state.insert("string.ston".to_string(), "number".to_string());
+    state.insert("string.new".to_string(), "string".to_string());
+    state.insert("string.concat".to_string(), "string".to_string());
+    state.insert("string.equals".to_string(), "number".to_string());
+    state.insert("string.compare".to_string(), "number".to_string());
+    state.insert("io.println".to_string(), "null".to_string());
+    state.insert("io.print".to_string(), "null".to_string());
+    state.insert("fs.clear".to_string(), "null".to_string());
+    state.insert("fs.readString".to_string(), "string".to_string());
+    state.insert("fs.close".to_string(), "null".to_string());
+    state.insert("fs.open".to_string(), "number".to_string());
+    state.insert("process.exit".to_string(), "null".to_string());
+    state.insert("env.args".to_string(), "array string".to_string());
+
+    return state;
+}
+
+/// Names of the implicitly imported core libraries, like `io`.
+fn core_libraries() -> Vec<String> {
+    let mut libraries: Vec<String> = vec![];
+    for name in stdlib_signatures().keys() {
+        if let Some((library, _)) = name.split_once('.') {
+            if !libraries.contains(&library.to_string()) {
+                libraries.push(library.to_string());
+            }
+        }
+    }
+
+    return libraries;
+}
+
+fn run_tests(debug: bool, rainbow: &config::Rainbow) {
     println!("\nRunning tests...\n");
 
     let test_files = vec![
@@ -68,77 +139,54 @@ fn run_tests(debug: bool) {
         "tests/lambda.myst",
         "tests/ascii.myst",
         "tests/files.myst",
+        "tests/casts.myst",
+        "tests/strings.myst",
+        "tests/interpolation.myst",
+        "tests/escapes.myst",
+        "tests/chars.myst",
+        "tests/dynamic_arrays.myst",
+        "tests/typed_arrays.myst",
+        "tests/maps.myst",
+        "tests/tuples.myst",
+        "tests/destructuring.myst",
+        "tests/closures.myst",
+        "tests/arrows.myst",
+        "tests/function_types.myst",
+        "tests/generics.myst",
+        "tests/named_args.myst",
+        "tests/variadic.myst",
+        "tests/modules.myst",
+        "tests/import_aliases.myst",
+        "tests/includes.myst",
+        "tests/env_args.myst",
     ];
+    // Tests run with arguments, like `myst run file.myst hello rainbow` would.
+    let argument_tests = vec![
+        ("tests/env_args_given.myst", vec!["hello", "rainbow"]),
+    ];
+    let tests: Vec<(&str, Vec<String>)> = test_files.into_iter().map(|file| (file, vec![]))
+        .chain(argument_tests.into_iter().map(|(file, args)| (file, args.into_iter().map(String::from).collect())))
+        .collect();
 
     let mut failed = 0;
-    for file in test_files.clone() {
-        let source = match std::fs::read_to_string(file) {
-            Ok(s) => s,
-            Err(e) => {
-                match e.kind() {
-                    ErrorKind::NotFound => {
-                        println!("❌ Could not find file '{}'", file);
-                        std::process::exit(1);
-                    },
-                    _=> {
-                        println!("❌ Could not read file '{}'", file);
-                        println!("{e}");
-                        std::process::exit(1);
-                    }
+    for (file, arguments) in tests.iter() {
+        let bytecode = match compile(Path::new(file), modules::search_path(), false) {
+            Ok(bytecode) => bytecode,
+            Err(errors) => {
+                println!("❌ Test failed: {} - ", file.red());
+                for error in errors {
+                    println!("   {}", error);
                 }
+
+                failed += 1;
+                continue;
             }
         };
 
-        let tokens = tokenizer::tokenize(source);
-        let mut parser = parser::Parser::new(tokens.clone());
-        let ast = parser.parse();
-
-        let mut wrapper = Wrapper::new();
-        wrapper.push(var!(
-            Value::TYPE(vec![Type::I64]),
-            Value::NAME("temp".to_string())
-        ));
-        wrapper.push(var!(
-            Value::TYPE(vec![Type::I64]),
-            Value::NAME("temp2".to_string())
-        ));
-        wrapper.push(var!(
-            Value::TYPE(vec![Type::I64]),
-            Value::NAME("temp3".to_string())
-        ));
-        wrapper.push(var!(
-            Value::TYPE(vec![Type::STRUCT("_".to_string())]),
-            Value::NAME("temp_struct".to_string())
-        ));
-
-        // Poor man's function signatures. @gromton12 kindly fix this.
-        let mut state: HashMap<String, String> = HashMap::new();
-        state.insert("string.ntos".to_string(), "struct".to_string());
-        state.insert("string.ston".to_string(), "number".to_string());
-        state.insert("string.new".to_string(), "struct".to_string());
-        state.insert("io.println".to_string(), "null".to_string());
-        state.insert("io.print".to_string(), "null".to_string());
-        state.insert("fs.clear".to_string(), "null".to_string());
-        state.insert("fs.readString".to_string(), "struct".to_string());
-        state.insert("fs.close".to_string(), "null".to_string());
-        state.insert("fs.open".to_string(), "number".to_string());
-
-        let implicit_imports = vec![
-            Expr::Import("io.rbb".to_string()),
-            Expr::Import("string.rbb".to_string()),
-            Expr::Import("fs.rbb".to_string()),
-        ];
-
-        let mut new_ast = implicit_imports.clone();
-        new_ast.extend(ast.clone());
-        let ast = new_ast;
-
-        engine::eval(ast, &mut wrapper, &mut state);
-
         let output_path = "output.rbb";
-        fs::write(output_path, wrapper.emit()).expect("Could not write bytecode to file");
+        fs::write(output_path, bytecode).expect("Could not write bytecode to file");
 
-        let ret = run_with_rb(output_path.to_string(), debug);
+        let ret = run_with_rb(output_path.to_string(), debug, rainbow, arguments);
         let expected_output = 69;
 
         if ret == expected_output {
@@ -153,164 +201,270 @@ fn run_tests(debug: bool) {
     }
 
     if failed == 0 {
-        println!("\n✔️ {}/{} tests passed\n", test_files.len(), test_files.len());
+        println!("\n✔️ {}/{} tests passed\n", tests.len(), tests.len());
     } else {
-        println!("\n❌ {}/{} tests passed\n", test_files.len() - failed, test_files.len());
+        println!("\n❌ {}/{} tests passed\n", tests.len() - failed, tests.len());
     }
 }
 
-fn main() {
-    let mut args: Vec<String> = env::args().collect();
-    args.remove(0);
-
-    let mut debug_mode = false;
-    let mut running_tests = false;
-    let mut source = String::new();
-    let mut output_path = String::from("out.rbb");
-    let mut user_overwrote_output_path = false;
-    let mut no_run = false;
-    let mut help = false;
-
-    let mut i = 0;
-    while i < args.len() {
-        let arg = args[i].clone();
-        match arg.as_str() {
-            "--" => {
-                i += 1;
-                continue;
-            }
-            "--debug" | "-d" => {
-                debug_mode = true;
-            },
-            "--test" | "-t" => {
-                running_tests = true;
-            }
-            "--output" | "-o" => {
-                output_path = args[i + 1].clone();
-                user_overwrote_output_path = true;
-                i += 1;
-            },
-            "--no-run" | "-n" => {
-                no_run = true;
-            },
-            "--help" | "-h" => {
-                help = true;
-            },
-            _ => {
-                source = arg;
-                if !user_overwrote_output_path {
-                    let mut parts: Vec<&str> = source.split(".").collect();
-                    parts.pop();
-                    output_path = format!("{}.rbb", parts.join("."));
-                }
-            }
-        }
-
-        i += 1;
-    }
-
-    if args.len() == 0 || help {
-        println!("\nUsage: {} {} {}\n", "myst".blue(), "[options]".cyan(), "<source file>".green());
-        println!("Options:");
-        println!("  {} {}:          Enable debug mode", "--debug".cyan(), "-d".cyan());
-        println!("  {} {}:           Run tests", "--test".cyan(), "-t".cyan());
-        println!("  {} {} {}:  Specify output file", "--output".cyan(), "-o".cyan(), "<file>".green());
-        println!("  {} {}:         Do not execute the output file", "--no-run".cyan(), "-n".cyan());
-        println!("  {} {}:           Display this help message", "--help".cyan(), "-h".cyan());
-        println!("\nExample:");
-        println!("  {} -d -o {} {}", "myst".blue(), "build.rbb".green(), "source.myst".green());
-        println!("  {} {} -d", "myst".blue(), "source.myst".green());
-        println!();
-        return;
-    }
-
-    if running_tests {
-        run_tests(debug_mode);
-        return;
-    } else if source == "" {
-        println!("❌ No source file provided.");
-        std::process::exit(1);
-    }
-
-    let source = match std::fs::read_to_string(source.clone()) {
-        Ok(s) => s,
+/// Reads the source file at `path`, exiting if it can not be read.
+fn read_source(path: &str) -> String {
+    match fs::read_to_string(path) {
+        Ok(source) => source,
         Err(e) => {
             match e.kind() {
                 ErrorKind::NotFound => {
-                    println!("❌ Could not find file '{}'", source);
+                    println!("❌ Could not find file '{}'", path);
                     std::process::exit(1);
                 },
                 _=> {
-                    println!("❌ Could not read file '{}'", source);
+                    println!("❌ Could not read file '{}'", path);
                     println!("{e}");
                     std::process::exit(1);
                 }
             }
         }
+    }
+}
+
+/// Parses the program in the file at `path`, links the modules it imports from `search_path`
+/// into it, and checks it. Returns the errors found in it otherwise.
+fn analyze(path: &Path, search_path: Vec<PathBuf>) -> Result<(Vec<Expr>, checker::Checker), Vec<String>> {
+    let file = path.display().to_string();
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => return Err(vec![format!("Could not read file '{}': {}", file, e)])
     };
 
     let tokens = tokenizer::tokenize(source);
-    if debug_mode {
-        println!("\n\nTokens: {:?}", tokens);
-    }
+    let ast = parser::Parser::for_file(tokens, path).parse();
+    let ast = modules::Linker::new(search_path, core_libraries()).link(ast, &file).map_err(|e| vec![e])?;
 
-    let mut parser = parser::Parser::new(tokens.clone());
-    let ast = parser.parse();
-    if debug_mode {
-        println!("\n\nAST: {:#?}", ast);
+    let mut checker = checker::Checker::new();
+    let errors = checker.check(&ast);
+    if errors.len() > 0 {
+        return Err(errors);
     }
 
-    let mut wrapper = Wrapper::new();
-    wrapper.push(var!(
-        Value::TYPE(vec![Type::I64]),
-        Value::NAME("temp".to_string())
-    ));
-    wrapper.push(var!(
-        Value::TYPE(vec![Type::I64]),
-        Value::NAME("temp2".to_string())
-    ));
-    wrapper.push(var!(
-        Value::TYPE(vec![Type::I64]),
-        Value::NAME("temp3".to_string())
-    ));
-    wrapper.push(var!(
-        Value::TYPE(vec![Type::STRUCT("_".to_string())]),
-        Value::NAME("temp_struct".to_string())
-    ));
+    Ok((ast, checker))
+}
 
-    let mut state: HashMap<String, String> = HashMap::new();
-    // Poor man's function signatures. @gromton12 kindly fix this.
-    state.insert("string.ntos".to_string(), "struct".to_string());
-    state.insert("string.ston".to_string(), "number".to_string());
-    state.insert("string.new".to_string(), "struct".to_string());
-    state.insert("io.println".to_string(), "null".to_string());
-    state.insert("io.print".to_string(), "null".to_string());
-    state.insert("fs.clear".to_string(), "null".to_string());
-    state.insert("fs.readString".to_string(), "struct".to_string());
-    state.insert("fs.close".to_string(), "null".to_string());
-    state.insert("fs.open".to_string(), "number".to_string());
+/// Compiles the program in the file at `path` to Rainbow bytecode, looking up the modules it
+/// imports in `search_path`. Returns the errors found in it otherwise.
+fn compile(path: &Path, search_path: Vec<PathBuf>, unchecked: bool) -> Result<Vec<u8>, Vec<String>> {
+    let file = path.display().to_string();
+    let (ast, checker) = analyze(path, search_path)?;
+
+    let mut wrapper = Wrapper::new();
+    let mut state = stdlib_signatures();
+    engine::set_option(&mut state, "file", &file);
+    if unchecked {
+        engine::set_option(&mut state, "unchecked", "true");
+    }
+    for (name, types) in checker.instances() {
+        engine::add_instance(&mut state, &name, &types);
+    }
+    engine::gen_preamble(&mut wrapper, &mut state);
 
-    let implicit_imports = vec![
+    let mut ast_with_imports = vec![
         Expr::Import("io.rbb".to_string()),
         Expr::Import("string.rbb".to_string()),
         Expr::Import("fs.rbb".to_string()),
+        // The index checks and other aborts of the preamble exit through `process`.
+        Expr::Import("process.rbb".to_string()),
     ];
+    // `env` is only in newer Rainbow cores, so programs that do not use it run on older ones.
+    if uses_library(&ast, "env") {
+        ast_with_imports.push(Expr::Import("env.rbb".to_string()));
+    }
+    ast_with_imports.extend(checker.resolve_calls(&ast));
+
+    engine::eval(ast_with_imports, &mut wrapper, &mut state);
+    Ok(wrapper.emit())
+}
+
+/// Whether `ast` uses the core library `library`, as in `process.exit(1)`.
+fn uses_library(ast: &Vec<Expr>, library: &str) -> bool {
+    let used = Cell::new(false);
+    for expr in ast.iter() {
+        expr.transform(&|expr| {
+            if let Expr::PropertyAccess(object, _) = &expr {
+                if let Expr::Identifier(name) = &**object {
+                    if name == library {
+                        used.set(true);
+                    }
+                }
+            }
+            expr
+        });
+    }
+
+    used.get()
+}
+
+/// Builds the project in `directory` from its `myst.toml` to `<output>/<name>.rbb`. The modules
+/// the entry point imports are compiled into it, as Rainbow can not load them on their own.
+fn build_project(directory: &Path, debug: bool, unchecked: bool) {
+    let manifest = match config::Manifest::find(directory) {
+        Some(manifest) => manifest,
+        None => {
+            println!("❌ Could not find {} in {} or any directory above it", config::MANIFEST, directory.display());
+            std::process::exit(1);
+        }
+    };
+    let project = match config::Manifest::read(&manifest).and_then(|manifest| config::Project::read(&manifest)) {
+        Ok(project) => project,
+        Err(e) => {
+            println!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+    println!("Building {} {}", project.name.blue(), project.version);
+
+    let mut search_path = project.sources.clone();
+    search_path.extend(project.libraries.clone());
+    search_path.extend(modules::search_path());
+
+    let output = project.output.join(format!("{}.rbb", project.name));
+    let bytecode = match compile(&project.entry, search_path, unchecked) {
+        Ok(bytecode) => bytecode,
+        Err(errors) => {
+            println!("❌ Could not build {}", project.entry.display().to_string().red());
+            for error in errors {
+                println!("   {}", error);
+            }
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = fs::create_dir_all(&project.output) {
+        println!("❌ Could not create directory {}: {}", project.output.display(), e);
+        std::process::exit(1);
+    }
+    if let Err(e) = fs::write(&output, bytecode) {
+        println!("❌ Could not write {}: {}", output.display(), e);
+        std::process::exit(1);
+    }
+    if debug {
+        println!("✔  Built {} from {}", output.display(), project.entry.display());
+    }
+    println!("✔  Built {} to {}", project.name, output.display().to_string().green());
+}
+
+/// Compiles the program in `file` to the bytecode file `output`, exiting on errors.
+fn compile_to(file: &str, output: &str, unchecked: bool, debug: bool) {
+    let bytecode = match compile(Path::new(file), modules::search_path(), unchecked) {
+        Ok(bytecode) => bytecode,
+        Err(errors) => {
+            for error in errors {
+                println!("❌ {}", error);
+            }
+            std::process::exit(1);
+        }
+    };
 
-    let mut new_ast = implicit_imports.clone();
-    new_ast.extend(ast.clone());
-    let ast = new_ast;
+    if let Err(e) = fs::write(output, bytecode) {
+        println!("❌ Could not write bytecode to {}: {}", output, e);
+        std::process::exit(1);
+    }
+    if debug {
+        println!("✔  Compiled to {}", output.green());
+    }
+}
 
-    engine::eval(ast.clone(), &mut wrapper, &mut state);
-    fs::write(output_path.clone(), wrapper.emit()).expect("Could not write bytecode to file");
+/// Formats the source files `files` in place, or with `check` only lists the ones that are
+/// not formatted.
+fn format_files(files: &Vec<String>, check: bool) {
+    let mut unformatted = 0;
+    for file in files.iter() {
+        let source = read_source(file);
+        let formatted = formatter::format(&source);
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            println!("❌ {} is not formatted", file.red());
+            unformatted += 1;
+        } else if let Err(e) = fs::write(file, formatted) {
+            println!("❌ Could not write {}: {}", file, e);
+            std::process::exit(1);
+        } else {
+            println!("✔  Formatted {}", file.green());
+        }
+    }
 
-    if debug_mode {
-        println!("✔  Compiled to {}", output_path.green());
+    if unformatted > 0 {
+        std::process::exit(1);
     }
+}
 
-    if !no_run {
-        let ret = run_with_rb(output_path, debug_mode);
-        if debug_mode {
-            println!("✔️ Rainbow exited with code {}", ret);
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    args.remove(0);
+
+    let options = match cli::parse(args) {
+        Ok(Some(options)) => options,
+        Ok(None) => return,
+        Err(e) => {
+            println!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let debug = options.has("--debug");
+    let unchecked = options.has("--unchecked");
+    match options.command.name {
+        "run" => {
+            let file = &options.files[0];
+            let output_path = options.value("--output").unwrap_or(Path::new(file).with_extension("rbb").display().to_string());
+            let rainbow = find_rainbow(options.value("--rainbow"), options.values("--lib"), Path::new(file));
+            compile_to(file, &output_path, unchecked, debug);
+
+            let ret = run_with_rb(output_path, debug, &rainbow, &options.arguments);
+            if debug {
+                println!("✔️ Rainbow exited with code {}", ret);
+            }
+        }
+        "build" => match options.files.first() {
+            Some(file) if file.ends_with(".myst") => {
+                let output_path = options.value("--output").unwrap_or(Path::new(file).with_extension("rbb").display().to_string());
+                compile_to(file, &output_path, unchecked, debug);
+            }
+            directory => {
+                if options.has("--output") {
+                    println!("❌ `--output` is only for building a single file, set `output` in {} to build a project elsewhere", config::MANIFEST);
+                    std::process::exit(1);
+                }
+                let directory = directory.cloned().unwrap_or(".".to_string());
+                build_project(Path::new(&directory), debug, unchecked);
+            }
+        }
+        "check" => {
+            let file = &options.files[0];
+            if let Err(errors) = analyze(Path::new(file), modules::search_path()) {
+                for error in errors {
+                    println!("❌ {}", error);
+                }
+                std::process::exit(1);
+            }
+            println!("✔  No errors in {}", file.green());
+        }
+        "test" => {
+            let rainbow = find_rainbow(options.value("--rainbow"), options.values("--lib"), Path::new("."));
+            run_tests(debug, &rainbow);
+        }
+        "fmt" => format_files(&options.files, options.has("--check")),
+        "tokens" => {
+            for token in tokenizer::tokenize(read_source(&options.files[0])) {
+                println!("{:?}", token);
+            }
+        }
+        "ast" => {
+            let file = &options.files[0];
+            let ast = parser::Parser::for_file(tokenizer::tokenize(read_source(file)), Path::new(file)).parse();
+            println!("{:#?}", ast);
         }
+        command => unreachable!("Unhandled command {}", command)
     }
 }
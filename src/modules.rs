@@ -0,0 +1,460 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::enums::*;
+use crate::tokenizer;
+use crate::parser;
+
+/// Finds the Myst modules a program imports with `import "utils";`, and links them into it.
+///
+/// Every module is compiled once, however often it is imported, where it is first imported. The
+/// names it declares at the top level are prefixed with its namespace, so `fn add` in `utils.myst`
+/// is compiled as `utils.add`, which is what `utils.add(1, 2)` in the importing file calls.
+/// Imports ending in `.rbb` are prebuilt Rainbow bytecode, and are left to the engine.
+///
+/// Only the names a module declares with `export` can be used by the files importing it. The rest
+/// are still prefixed, so that they do not clash with the names of other modules.
+///
+/// `import "io" as out;` and `import { println as say } from "io";` give a module or library
+/// another name, and are resolved here too. The engine only ever sees the real names.
+pub struct Linker {
+    search_path: Vec<PathBuf>,
+    libraries: Vec<String>, // The Rainbow libraries every program imports, such as `io`
+    loading: Vec<PathBuf>, // The modules being loaded, each imported by the one before it
+    loaded: HashMap<PathBuf, Module>, // Every module loaded so far
+    library_namespaces: Vec<String> // The namespaces of the Rainbow libraries imported so far
+}
+
+/// What the files importing a module need to know about it.
+#[derive(Clone)]
+struct Module {
+    namespace: String,
+    names: Vec<String>, // Every name the module declares at the top level
+    exports: Vec<String>, // The names it declares with `export`
+    library: bool // Whether it is prebuilt Rainbow bytecode, whose names are not known
+}
+
+impl Module {
+    /// The qualified name of `member`, used from this module imported as `alias`.
+    fn member(&self, alias: &String, member: &String) -> Result<String, String> {
+        if !self.library && !self.names.contains(member) {
+            return Err(format!("Module `{}` has no `{}`", alias, member));
+        }
+        if !self.library && !self.exports.contains(member) {
+            return Err(format!("`{}` is private to module `{}`, declare it with `export` to use it here", member, alias));
+        }
+
+        Ok(format!("{}.{}", self.namespace, member))
+    }
+}
+
+impl Linker {
+    pub fn new(search_path: Vec<PathBuf>, libraries: Vec<String>) -> Self {
+        Self {
+            search_path,
+            libraries,
+            loading: vec![],
+            loaded: HashMap::new(),
+            library_namespaces: vec![]
+        }
+    }
+
+    /// Links every module imported by the program `ast`, read from the file at `path`, into it.
+    pub fn link(&mut self, ast: Vec<Expr>, path: &str) -> Result<Vec<Expr>, String> {
+        let path = match fs::canonicalize(path) {
+            Ok(path) => path,
+            Err(_) => PathBuf::from(path)
+        };
+
+        self.loading.push(path.clone());
+        let result = self.link_module(ast, &path, None);
+        self.loading.pop();
+
+        // Functions of Rainbow libraries are called through their library, like `io.println(x)`.
+        let libraries = &self.library_namespaces;
+        Ok(result?.iter().map(|expr| expr.transform(&|expr| match expr {
+            Expr::CallFunc(callee, args) => {
+                let qualified = match &*callee {
+                    Expr::Identifier(name) => name.split_once('.').map(|(library, function)| (library.to_string(), function.to_string())),
+                    _ => None
+                };
+                match qualified {
+                    Some((library, function)) if libraries.contains(&library) => Expr::PropertyAccess(
+                        Box::new(Expr::Identifier(library)),
+                        Box::new(Expr::CallFunc(Box::new(Expr::Identifier(function)), args))
+                    ),
+                    _ => Expr::CallFunc(callee, args)
+                }
+            }
+            expr => expr
+        })).collect())
+    }
+
+    /// Replaces the imports in the module `ast` with the code of the modules they import, and
+    /// qualifies the names it uses from them. With a `namespace`, the names the module declares
+    /// itself are prefixed with it too, and so are its anonymous functions, which every file
+    /// numbers from `anonymous_0`.
+    fn link_module(&mut self, ast: Vec<Expr>, path: &Path, namespace: Option<&String>) -> Result<Vec<Expr>, String> {
+        let declared = declared_names(&ast, false);
+        let mut renames = HashMap::new();
+        if let Some(namespace) = namespace {
+            for name in declared.iter().chain(anonymous_functions(&ast).iter()) {
+                renames.insert(name.clone(), format!("{}.{}", namespace, name));
+            }
+        }
+
+        let mut namespaces: HashMap<String, Module> = HashMap::new();
+        let mut modules: HashMap<usize, Vec<Expr>> = HashMap::new();
+        for (i, expr) in ast.iter().enumerate() {
+            let name = match expr {
+                Expr::Import(name) | Expr::ImportAs(name, _) | Expr::ImportNames(name, _) => name,
+                _ => continue
+            };
+
+            let (code, module) = self.import(name, path)?;
+            match expr {
+                Expr::ImportAs(_, alias) => {
+                    namespaces.insert(alias.clone(), module);
+                }
+                Expr::ImportNames(_, names) => {
+                    for (member, alias) in names.iter() {
+                        if declared.contains(alias) {
+                            return Err(format!("`{}` is imported from `{}`, and declared in {} too", alias, name, file_name(path)));
+                        }
+                        renames.insert(alias.clone(), module.member(&module.namespace, member)?);
+                    }
+                }
+                _ => {
+                    namespaces.insert(module.namespace.clone(), module);
+                }
+            }
+            modules.insert(i, code);
+        }
+
+        let mut linked = vec![];
+        for (i, expr) in ast.iter().enumerate() {
+            match modules.remove(&i) {
+                Some(code) => linked.extend(code),
+                None => {
+                    let expr = qualify(&unexport(expr), &namespaces)?;
+                    linked.push(rename(&expr, &renames));
+                }
+            }
+        }
+
+        Ok(linked)
+    }
+
+    /// Finds what `import "name"` in the file at `importer` imports. Returns the code to put in
+    /// place of the import, and what the importing file needs to know about it.
+    fn import(&mut self, name: &String, importer: &Path) -> Result<(Vec<Expr>, Module), String> {
+        // Prebuilt Rainbow bytecode is imported by the engine.
+        if name.ends_with(".rbb") {
+            let namespace = match Path::new(name).file_stem() {
+                Some(stem) => stem.to_string_lossy().to_string(),
+                None => return Err(format!("Invalid module name `{}`", name))
+            };
+            return Ok((vec![Expr::Import(name.clone())], self.library(&namespace)));
+        }
+
+        match self.resolve(name, importer) {
+            Ok(path) => self.load(&path, name),
+            // Every program imports the core libraries already.
+            Err(_) if self.libraries.contains(name) => Ok((vec![], self.library(name))),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// The Rainbow library `namespace`, whose functions are all used as they are.
+    fn library(&mut self, namespace: &String) -> Module {
+        if !self.library_namespaces.contains(namespace) {
+            self.library_namespaces.push(namespace.clone());
+        }
+
+        Module {
+            namespace: namespace.clone(),
+            names: vec![],
+            exports: vec![],
+            library: true
+        }
+    }
+
+    /// Loads the module at `path`, imported as `name`. Returns its linked code and what importing
+    /// files need to know about it. The code is empty if the module was loaded before.
+    fn load(&mut self, path: &PathBuf, name: &String) -> Result<(Vec<Expr>, Module), String> {
+        if self.loading.contains(path) {
+            let mut cycle: Vec<String> = self.loading.iter()
+                .skip_while(|loading| *loading != path)
+                .map(|loading| file_name(loading))
+                .collect();
+            cycle.push(file_name(path));
+            return Err(format!("Import cycle: {}", cycle.join(" -> ")));
+        }
+
+        if let Some(module) = self.loaded.get(path) {
+            return Ok((vec![], module.clone()));
+        }
+
+        let namespace = match Path::new(name).file_stem() {
+            Some(stem) => stem.to_string_lossy().to_string(),
+            None => return Err(format!("Invalid module name `{}`", name))
+        };
+        if self.libraries.contains(&namespace) {
+            return Err(format!("Module {} has the same name as the core library `{}`, rename it", path.display(), namespace));
+        }
+        for (other, module) in self.loaded.iter() {
+            if module.namespace == namespace {
+                return Err(format!(
+                    "Modules {} and {} are both named `{}`, rename one of them",
+                    other.display(), path.display(), namespace
+                ));
+            }
+        }
+
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => return Err(format!("Could not read module {}: {}", path.display(), e))
+        };
+        let tokens = tokenizer::tokenize(source);
+        let ast = parser::Parser::for_file(tokens, path).parse();
+
+        // A module runs where it is imported, a `return` would end the importing program.
+        if ast.iter().any(|expr| matches!(expr, Expr::Return(_))) {
+            return Err(format!("Modules can not `return` at the top level, in {}", path.display()));
+        }
+
+        let module = Module {
+            namespace: namespace.clone(),
+            names: declared_names(&ast, false),
+            exports: declared_names(&exported(&ast), false),
+            library: false
+        };
+
+        self.loading.push(path.clone());
+        let code = self.link_module(ast, path, Some(&namespace));
+        self.loading.pop();
+        let code = code?;
+
+        self.loaded.insert(path.clone(), module.clone());
+        Ok((code, module))
+    }
+
+    /// Finds the file of the module `name` imported by the file at `importer`. Modules are looked
+    /// up next to the importing file first, then in every directory of the search path.
+    fn resolve(&self, name: &String, importer: &Path) -> Result<PathBuf, String> {
+        let file = match name.ends_with(".myst") {
+            true => name.clone(),
+            false => format!("{}.myst", name)
+        };
+
+        let mut dirs = vec![];
+        if let Some(dir) = importer.parent() {
+            dirs.push(dir.to_path_buf());
+        }
+        dirs.extend(self.search_path.iter().cloned());
+
+        for dir in dirs.iter() {
+            let candidate = dir.join(&file);
+            if candidate.is_file() {
+                return Ok(fs::canonicalize(&candidate).unwrap_or(candidate));
+            }
+        }
+
+        let looked_in: Vec<String> = dirs.iter().map(|dir| dir.display().to_string()).collect();
+        Err(format!(
+            "Could not find module `{}` imported by {}, looked in: {}",
+            name, file_name(importer), looked_in.join(", ")
+        ))
+    }
+}
+
+/// The directories in `MYST_PATH`, separated by `:`, where modules are looked up when they are
+/// not next to the file importing them.
+pub fn search_path() -> Vec<PathBuf> {
+    match env::var("MYST_PATH") {
+        Ok(paths) => paths.split(':').filter(|path| !path.is_empty()).map(PathBuf::from).collect(),
+        Err(_) => vec![]
+    }
+}
+
+/// The declarations in `ast` marked with `export`.
+fn exported(ast: &Vec<Expr>) -> Vec<Expr> {
+    ast.iter().filter_map(|expr| match expr {
+        Expr::Export(declaration) => Some(*declaration.clone()),
+        _ => None
+    }).collect()
+}
+
+/// `expr` without any `export` in it. Exports only matter to the linker.
+fn unexport(expr: &Expr) -> Expr {
+    match expr {
+        Expr::Export(declaration) => unexport(declaration),
+        _ => expr.transform(&|expr| match expr {
+            Expr::Export(declaration) => *declaration,
+            expr => expr
+        })
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    match path.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => path.display().to_string()
+    }
+}
+
+/// The names declared by the statements in `body`: functions, classes and variables. With `deep`,
+/// variables declared in the blocks of conditions and loops count too, but never the ones inside
+/// nested functions.
+fn declared_names(body: &Vec<Expr>, deep: bool) -> Vec<String> {
+    let mut names = vec![];
+    for expr in body.iter() {
+        match expr {
+            Expr::Export(declaration) => names.extend(declared_names(&vec![*declaration.clone()], deep)),
+            Expr::DecFunc(name, _, _, _) | Expr::DecClass(name, _) => names.push(name.clone()),
+            Expr::BinOp(Operator::Declare(_), left, _) => {
+                match &**left {
+                    Expr::Identifier(name) => names.push(name.clone()),
+                    Expr::Tuple(items) | Expr::Array(items) => {
+                        for item in items.iter() {
+                            match item {
+                                Expr::Identifier(name) => names.push(name.clone()),
+                                Expr::ArrayAccess(name, _, _) => {
+                                    if let Expr::Identifier(name) = &**name {
+                                        names.push(name.clone());
+                                    }
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                    Expr::Map(fields) => {
+                        for (_, name) in fields.iter() {
+                            if let Expr::Identifier(name) = name {
+                                names.push(name.clone());
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Expr::If(_, body, else_body) if deep => {
+                names.extend(declared_names(body, deep));
+                names.extend(declared_names(else_body, deep));
+            }
+            Expr::While(_, body) if deep => names.extend(declared_names(body, deep)),
+            Expr::For(loop_names, _, body, _) if deep => {
+                names.extend(loop_names.iter().cloned());
+                names.extend(declared_names(body, deep));
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+/// The names of the anonymous functions anywhere in `ast`.
+fn anonymous_functions(ast: &Vec<Expr>) -> Vec<String> {
+    let names = RefCell::new(vec![]);
+    for expr in ast.iter() {
+        unexport(expr).transform(&|expr| {
+            if let Expr::DecFunc(name, _, _, _) = &expr {
+                if name.starts_with("anonymous_") {
+                    names.borrow_mut().push(name.clone());
+                }
+            }
+            expr
+        });
+    }
+    names.into_inner()
+}
+
+/// Renames the variables and functions in `expr` that are in `renames`, except where a parameter
+/// or local variable of the same name hides them.
+fn rename(expr: &Expr, renames: &HashMap<String, String>) -> Expr {
+    match expr {
+        Expr::Identifier(name) => Expr::Identifier(renames.get(name).cloned().unwrap_or(name.clone())),
+        Expr::DecFunc(name, params, body, typ) => {
+            let mut inner = renames.clone();
+            for param in params.iter() {
+                if let Expr::Parameter(param_name, _, _) | Expr::RestParameter(param_name, _) = param {
+                    inner.remove(param_name);
+                }
+            }
+            for local in declared_names(body, true) {
+                inner.remove(&local);
+            }
+
+            // Default values are evaluated by the caller, where the parameters do not exist.
+            let params = params.iter().map(|param| param.map_children(&mut |default| rename(default, renames))).collect();
+            let body = body.iter().map(|expr| rename(expr, &inner)).collect();
+            let name = renames.get(name).cloned().unwrap_or(name.clone());
+            Expr::DecFunc(name, params, Box::new(body), typ.clone())
+        }
+        Expr::For(names, iterable, body, line) => {
+            let mut inner = renames.clone();
+            for name in names.iter() {
+                inner.remove(name);
+            }
+            let body = body.iter().map(|expr| rename(expr, &inner)).collect();
+            Expr::For(names.clone(), Box::new(rename(iterable, renames)), Box::new(body), *line)
+        }
+        // Properties and methods are not variables, only the object and arguments are.
+        Expr::PropertyAccess(object, property) => {
+            let property = match &**property {
+                Expr::CallFunc(method, args) => {
+                    let args = args.iter().map(|arg| rename(arg, renames)).collect();
+                    Expr::CallFunc(method.clone(), Box::new(args))
+                }
+                property => property.clone()
+            };
+            Expr::PropertyAccess(Box::new(rename(object, renames)), Box::new(property))
+        }
+        _ => expr.map_children(&mut |child| rename(child, renames))
+    }
+}
+
+/// Turns `utils.add(1, 2)` and `utils.count` into uses of the qualified names `utils.add` and
+/// `utils.count`, for every module in `namespaces`, by the name it is imported as.
+fn qualify(expr: &Expr, namespaces: &HashMap<String, Module>) -> Result<Expr, String> {
+    if namespaces.is_empty() {
+        return Ok(expr.clone());
+    }
+
+    let errors = RefCell::new(vec![]);
+    let qualified = expr.transform(&|expr| match expr {
+        Expr::PropertyAccess(object, property) => {
+            let (alias, module) = match &*object {
+                Expr::Identifier(alias) if namespaces.contains_key(alias) => (alias, &namespaces[alias]),
+                _ => return Expr::PropertyAccess(object, property)
+            };
+
+            let member = match &*property {
+                Expr::CallFunc(callee, _) => callee.describe(),
+                Expr::Identifier(name) => name.clone(),
+                _ => String::new()
+            };
+            let name = match module.member(alias, &member) {
+                Ok(name) => Expr::Identifier(name),
+                Err(e) => {
+                    errors.borrow_mut().push(e);
+                    return Expr::PropertyAccess(object, property);
+                }
+            };
+
+            match *property {
+                Expr::CallFunc(_, args) => Expr::CallFunc(Box::new(name), args),
+                _ => name
+            }
+        }
+        expr => expr
+    });
+
+    match errors.into_inner().first() {
+        Some(error) => Err(error.clone()),
+        None => Ok(qualified)
+    }
+}
@@ -1,29 +1,80 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
 use crate::enums::*;
+use crate::tokenizer;
 
 pub struct Parser {
     tokens: Vec<Token>,
     expressions: Vec<Expr>,
     current: usize,
-    line: usize,
+    lines: Vec<usize>,
+    locals: Vec<Vec<String>>, // Variables declared so far in each function being parsed, innermost last
+    signatures: HashMap<String, Vec<(String, MType)>>, // Parameters of the functions declared so far
+    type_params: Vec<String>, // Type parameters of the generic functions and classes being parsed
+    file: PathBuf, // The file being parsed, which the files it includes are relative to
+    including: Vec<PathBuf>, // The files including this one, each included by the one before it
+    included: Vec<PathBuf>, // Every file included so far, each is only included once
     anonymous_counter: u128
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
+        // `NewLine`s are only kept around to know which line each token is on.
+        let mut lines = vec![];
+        let mut line = 1;
+        let tokens = tokens.into_iter().filter(|token| {
+            match token {
+                Token::NewLine => {
+                    line += 1;
+                    false
+                }
+                _ => {
+                    lines.push(line);
+                    true
+                }
+            }
+        }).collect();
+
         Self {
             tokens,
             current: 0,
-            line: 0,
+            lines,
+            locals: vec![],
+            signatures: HashMap::new(),
+            type_params: vec![],
+            file: PathBuf::new(),
+            including: vec![],
+            included: vec![],
             expressions: vec![],
             anonymous_counter: 0
         }
     }
 
+    /// A parser for the source of the file at `path`, so that it can `include` other files.
+    pub fn for_file(tokens: Vec<Token>, path: &Path) -> Self {
+        let mut parser = Self::new(tokens);
+        parser.file = match fs::canonicalize(path) {
+            Ok(path) => path,
+            Err(_) => path.to_path_buf()
+        };
+
+        parser
+    }
+
     /// Parses the entire token stream.
     ///
     /// This is the main entry point for the parser.
     pub fn parse(&mut self) -> Vec<Expr> {
         while self.current < self.tokens.len() {
+            // An included file takes the place of the `include`, statements and all.
+            if let Token::Include = self.peek() {
+                let included = self.parse_include();
+                self.expressions.extend(included);
+                continue;
+            }
+
             let expr = self.parse_statement();
             self.expressions.push(expr);
         }
@@ -38,20 +89,12 @@ impl Parser {
             Token::EOF => {
                 Expr::EOF
             }
-            Token::NewLine => {
-                self.line += 1;
-                self.advance(); // Consume `NewLine`
-                self.parse_statement()
-            },
             Token::Plus | Token::Minus | Token::Star | Token::Slash | Token::RParen | Token::LBracket | Token::Comma | Token::Semicolon => {
                 self.parse_expression()
             },
             Token::Not | Token::LArrow | Token::RArrow | Token::Equality => {
                 self.parse_comparison()
             }
-            Token::Equal => {
-                self.parse_assignment()
-            },
             Token::Dot => {
                 self.advance(); // Consume `.`
                 self.parse_property_access()
@@ -60,7 +103,11 @@ impl Parser {
                 self.parse_declaration()
             },
             Token::Identifier(_name) => {
-                self.parse_identifier()
+                let target = self.parse_identifier();
+                match self.peek() {
+                    Token::Equal => self.parse_assignment(target),
+                    _ => target
+                }
             },
             Token::Func => {
                 self.parse_function(false)
@@ -80,9 +127,18 @@ impl Parser {
             Token::Number(_) => {
                 self.parse_number()
             },
+            Token::Char(_) => {
+                self.parse_char()
+            },
             Token::Import => {
                 self.parse_import()
             },
+            Token::Export => {
+                self.parse_export()
+            },
+            Token::Include => {
+                panic!("Files can only be included at the top level, not in functions or blocks")
+            },
             Token::Class => {
                 self.parse_class()
             },
@@ -90,7 +146,10 @@ impl Parser {
                 self.parse_string()
             }
             Token::LParen => {
-                self.parse_function(true)
+                self.parse_parenthesized()
+            },
+            Token::LCurly => {
+                self.parse_map()
             },
             _ => todo!("Token: {:?}", self.peek())
         }
@@ -99,41 +158,88 @@ impl Parser {
     /// Parses a type annotation.
     fn parse_type(&mut self) -> MType {
         self.advance(); // Consume `:`
+        self.parse_type_name()
+    }
+
+    /// Parses a type name, such as `Number` or `Function<Number>`, without a leading `:`.
+    fn parse_type_name(&mut self) -> MType {
         match self.advance() {
-            Token::Identifier(name) => {
-                let mut result_type = MType::Undefined;
-                match name.as_str() {
-                    "Number" => {
-                        result_type = MType::Number;
-                    }
-                    "String" => {
-                        result_type = MType::String;
-                    }
-                    "Function" => {
-                        result_type = MType::Function;
-                    }
-                    "Class" => {
-                        result_type = MType::Class;
-                    }
-                    "Struct" => {
-                        result_type = MType::Struct;
-                    }
-                    "Null" => {
-                        result_type = MType::Null;
+            Token::LParen => { // (TypeA, TypeB)
+                let mut types = vec![];
+                loop {
+                    match self.peek() {
+                        Token::RParen => {
+                            self.advance(); // Consume `)`
+                            break;
+                        }
+                        Token::Comma => {
+                            self.advance(); // Consume `,`
+                        }
+                        _ => types.push(self.parse_type_name())
                     }
-                    "Undefined" => {
-                        result_type = MType::Undefined;
+                }
+
+                MType::Tuple(types)
+            }
+            Token::Identifier(name) => {
+                let mut result_type = match name.as_str() {
+                    "Number" => MType::Number,
+                    "I64" => MType::I64,
+                    "I32" => MType::I32,
+                    "I16" => MType::I16,
+                    "I8" => MType::I8,
+                    "U64" => MType::U64,
+                    "U32" => MType::U32,
+                    "U16" => MType::U16,
+                    "U8" => MType::U8,
+                    "F64" => MType::F64,
+                    "F32" => MType::F32,
+                    "F16" => MType::F16,
+                    "String" => MType::String,
+                    "Char" => MType::Char,
+                    "Array" => MType::Array,
+                    "Map" => MType::Map(Box::new(MType::Undefined), Box::new(MType::Undefined)),
+                    "Function" => MType::Function,
+                    "Fn" => { // Fn(ParamType, ...) -> ReturnType
+                        let params = match self.parse_type_name() {
+                            MType::Tuple(params) => params,
+                            typ => panic!("Expected parameter types after `Fn`, got {:?}", typ)
+                        };
+                        let returns = match self.peek() {
+                            Token::ThinArrow => {
+                                self.advance(); // Consume `->`
+                                self.parse_type_name()
+                            }
+                            _ => MType::Null
+                        };
+
+                        MType::Fn(params, Box::new(returns))
                     }
+                    "Class" => MType::Class,
+                    "Struct" => MType::Struct,
+                    "Null" => MType::Null,
+                    "Undefined" => MType::Undefined,
+                    _ if self.type_params.contains(&name) => MType::Generic(name.clone()),
                     _ => {
                         panic!("Unknown type: {:?}", name);
                     }
-                }
+                };
 
                 match self.peek() {
+                    Token::LArrow if name == "Map" => { // Map<KeyType, ValueType>
+                        self.advance(); // Consume `<`
+                        let key = self.parse_type_name();
+                        self.advance(); // Consume `,`
+                        let value = self.parse_type_name();
+                        self.advance(); // Consume `>`
+
+                        result_type = MType::Map(Box::new(key), Box::new(value));
+                    }
                     Token::LArrow => { // ParentType<ChildType>
-                        self.advance();
+                        self.advance(); // Consume `<`
                         let parent = result_type;
-                        let child = self.parse_type();
+                        let child = self.parse_type_name();
+                        self.advance(); // Consume `>`
 
                         result_type = MType::Nested(
                             Box::new(parent),
@@ -151,6 +257,16 @@ impl Parser {
         }
     }
 
+    /// Parses the `as Type` suffix of a cast, wrapping `value`.
+    fn parse_cast(&mut self, value: Expr) -> Expr {
+        self.advance(); // Consume `as`
+        let typ = self.parse_type_name();
+
+        let result = Expr::Cast(Box::new(value), typ);
+
+        return result;
+    }
+
     /// Parses a class declaration.
     fn parse_class(&mut self) -> Expr {
         self.advance(); // Consume `class`
@@ -159,6 +275,10 @@ impl Parser {
             _ => panic!("Expected an identifier for class declaration, got {:?}", self.peek())
         };
 
+        // Classes are not compiled yet, so there is nothing to give type arguments to.
+        if let Token::LArrow = self.peek() {
+            panic!("The class {} can not have type parameters, only functions can be generic", name);
+        }
         let body = self.parse_block();
 
         let result = Expr::DecClass(
@@ -183,14 +303,29 @@ impl Parser {
         return result;
     }
 
-    /// Parses a for loop.
+    /// Parses a for loop, either `for item of items` or `for (key, value) of map`.
     fn parse_for(&mut self) -> Expr {
         self.advance(); // Consume `for`
-        let iterator = match self.advance() {
-            Token::Identifier(name) => name,
+        let line = self.line();
+        let mut iterator = vec![];
+        match self.advance() {
+            Token::Identifier(name) => iterator.push(name),
+            Token::LParen => {
+                loop {
+                    match self.advance() {
+                        Token::Identifier(name) => iterator.push(name),
+                        Token::Comma => {},
+                        Token::RParen => break,
+                        token => panic!("Expected an identifier for for loop, got {:?}", token)
+                    }
+                }
+            }
             _ => panic!("Expected an identifier for for loop, got {:?}", self.peek())
         };
-        self.advance(); // Consume `in`
+        for name in iterator.iter() {
+            self.declare_local(name);
+        }
+        self.advance(); // Consume `of`
 
         let iterable = self.parse_statement();
         let body = self.parse_block();
@@ -198,7 +333,8 @@ impl Parser {
         let result = Expr::For(
             iterator,
             Box::new(iterable),
-            Box::new(body)
+            Box::new(body),
+            line
         );
 
         return result;
@@ -209,13 +345,13 @@ impl Parser {
     /// Can also parse a unary, like `1` for `true`.
     fn parse_comparison(&mut self) -> Expr {
         let left = match self.peek() {
-            Token::String(value) => Expr::String(value),
-            Token::Identifier(name) => Expr::Identifier(name),
-            Token::Number(value) => Expr::Number(value),
+            Token::String(_) => self.parse_string(),
+            Token::Identifier(_) => self.parse_identifier(),
+            Token::Number(_) => self.parse_number(),
+            Token::Char(_) => self.parse_char(),
             _ => panic!("Expected a number, string, or identifier, got {:?}", self.peek())
         };
 
-        self.advance();
         match self.peek() {
             Token::Not => {
                 self.advance(); // Consume `!`
@@ -351,12 +487,56 @@ impl Parser {
     /// Parses an import statement.
     fn parse_import(&mut self) -> Expr {
         self.advance(); // Consume `import`
+
+        // `import { println as say, print } from "io";`
+        let mut names = None;
+        if let Token::LCurly = self.peek() {
+            self.advance(); // Consume `{`
+            let mut imported = vec![];
+            loop {
+                match self.advance() {
+                    Token::RCurly => break,
+                    Token::Comma => continue,
+                    Token::Identifier(name) => {
+                        let alias = match self.peek() {
+                            Token::As => {
+                                self.advance(); // Consume `as`
+                                match self.advance() {
+                                    Token::Identifier(alias) => alias,
+                                    token => panic!("Expected a name after `as`, got {:?}", token)
+                                }
+                            }
+                            _ => name.clone()
+                        };
+                        imported.push((name, alias));
+                    }
+                    token => panic!("Expected a name to import, got {:?}", token)
+                }
+            }
+
+            match self.advance() {
+                Token::Identifier(from) if from == "from" => {}
+                token => panic!("Expected `from` after the imported names, got {:?}", token)
+            }
+            names = Some(imported);
+        }
+
         let name = match self.advance() {
             Token::String(name) => name,
             _ => panic!("Expected an identifier for import, got {:?}", self.peek())
         };
 
-        let result = Expr::Import(name);
+        let result = match (names, self.peek()) {
+            (Some(names), _) => Expr::ImportNames(name, names),
+            (None, Token::As) => {
+                self.advance(); // Consume `as`
+                match self.advance() {
+                    Token::Identifier(alias) => Expr::ImportAs(name, alias),
+                    token => panic!("Expected a name after `as`, got {:?}", token)
+                }
+            }
+            (None, _) => Expr::Import(name)
+        };
 
         match self.peek() {
             Token::Semicolon => {
@@ -368,6 +548,84 @@ impl Parser {
         return result;
     }
 
+    /// Parses `export` before a function, variable or class declaration, which lets the modules
+    /// importing this one use it.
+    fn parse_export(&mut self) -> Expr {
+        self.advance(); // Consume `export`
+        if !self.locals.is_empty() {
+            panic!("Only top level declarations can be exported");
+        }
+
+        let declaration = match self.peek() {
+            Token::Func | Token::Let | Token::Class => self.parse_statement(),
+            Token::Identifier(name) if name == "type" => panic!("Type aliases can not be exported, there are no `type` declarations yet"),
+            token => panic!("Expected `fn`, `let` or `class` after `export`, got {:?}", token)
+        };
+
+        Expr::Export(Box::new(declaration))
+    }
+
+    /// Parses `include "file.myst";`, returning the statements of the included file.
+    ///
+    /// The file is found relative to the one including it. Like with an include guard, a file
+    /// already included before is not included again, and files can not include each other.
+    /// The statements are put between `Expr::Include` markers, so that runtime errors in them
+    /// name the file they are in.
+    fn parse_include(&mut self) -> Vec<Expr> {
+        self.advance(); // Consume `include`
+        let name = match self.advance() {
+            Token::String(name) => name,
+            token => panic!("Expected a file name to include, got {:?}", token)
+        };
+        if let Token::Semicolon = self.peek() {
+            self.advance(); // Consume `;`
+        }
+
+        let directory = self.file.parent().unwrap_or(Path::new("")).to_path_buf();
+        let path = match fs::canonicalize(directory.join(&name)) {
+            Ok(path) => path,
+            Err(e) => panic!("Could not include {} in {}: {}", name, self.file.display(), e)
+        };
+
+        if path == self.file || self.including.contains(&path) {
+            let mut cycle: Vec<String> = self.including.iter().chain([&self.file, &path])
+                .skip_while(|file| **file != path)
+                .map(|file| file.file_name().unwrap_or_default().to_string_lossy().to_string())
+                .collect();
+            if cycle.len() == 1 {
+                cycle.push(cycle[0].clone());
+            }
+            panic!("Include cycle: {}", cycle.join(" -> "));
+        }
+        if self.included.contains(&path) {
+            return vec![];
+        }
+        self.included.push(path.clone());
+
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => panic!("Could not include {}: {}", path.display(), e)
+        };
+
+        let mut parser = Parser::for_file(tokenizer::tokenize(source), &path);
+        parser.including = self.including.clone();
+        parser.including.push(self.file.clone());
+        parser.included = self.included.clone();
+        parser.signatures = self.signatures.clone();
+        parser.anonymous_counter = self.anonymous_counter;
+        let ast = parser.parse();
+
+        // What the included file declares is known to the rest of this one.
+        self.included = parser.included;
+        self.signatures = parser.signatures;
+        self.anonymous_counter = parser.anonymous_counter;
+
+        let mut statements = vec![Expr::Include(display_path(&path))];
+        statements.extend(ast);
+        statements.push(Expr::Include(display_path(&self.file)));
+        statements
+    }
+
     /// Parses a return statement.
     fn parse_return(&mut self) -> Expr {
         self.advance(); // Consume `return`
@@ -402,10 +660,6 @@ impl Parser {
                     self.advance(); // Consume `)`
                     break;
                 },
-                Token::LParen => {
-                    let arg = self.parse_function(true);
-                    args.push(arg);
-                },
                 Token::Plus => {
                     break;
                 }
@@ -420,6 +674,17 @@ impl Parser {
                 Token::EOF => {
                     break;
                 },
+                Token::Ellipsis => {
+                    self.advance(); // Consume `...`
+                    let value = self.parse_expression();
+                    args.push(Expr::Spread(Box::new(value)));
+                }
+                Token::Identifier(arg_name) if matches!(self.tokens.get(self.current + 1), Some(Token::Colon)) => {
+                    self.advance(); // Consume identifier
+                    self.advance(); // Consume `:`
+                    let value = self.parse_expression();
+                    args.push(Expr::NamedArg(arg_name, Box::new(value)));
+                }
                 _ => {
                     let arg = self.parse_expression();
                     args.push(arg);
@@ -427,6 +692,24 @@ impl Parser {
             }
         }
 
+        // Anonymous functions without a return type take the one the parameter expects.
+        if let Some(signature) = self.signatures.get(&name) {
+            for (i, arg) in args.iter_mut().enumerate() {
+                match arg {
+                    Expr::NamedArg(arg_name, value) => {
+                        if let Some((_, typ)) = signature.iter().find(|(name, _)| name == arg_name) {
+                            infer_function_type(value, typ);
+                        }
+                    }
+                    _ => {
+                        if let Some((_, typ)) = signature.get(i) {
+                            infer_function_type(arg, typ);
+                        }
+                    }
+                }
+            }
+        }
+
         let result = Expr::CallFunc(
             Box::new(Expr::Identifier(name)),
             Box::new(args)
@@ -498,6 +781,8 @@ impl Parser {
             self.anonymous_counter += 1;
         }
 
+        let type_params = self.parse_type_params();
+
         self.advance(); // Consume `(`
 
         let params = self.parse_params();
@@ -509,7 +794,33 @@ impl Parser {
             _ => {}
         }
 
-        let body = self.parse_block();
+        let mut locals = vec![];
+        for param in params.iter() {
+            if let Expr::Parameter(name, _, _) | Expr::RestParameter(name, _) = param {
+                locals.push(name.clone());
+            }
+        }
+        if !is_anonymous {
+            let signature = params.iter().map(|param| match param {
+                Expr::Parameter(name, typ, _) | Expr::RestParameter(name, typ) => (name.clone(), typ.clone()),
+                _ => (String::new(), MType::Undefined)
+            }).collect();
+            self.signatures.insert(name.clone(), signature);
+        }
+
+        self.locals.push(locals);
+        let body = match self.peek() {
+            Token::FatArrow => { // (a, b) => a + b
+                self.advance(); // Consume `=>`
+                match self.peek() {
+                    Token::LCurly => self.parse_block(),
+                    _ => vec![Expr::Return(Box::new(self.parse_expression()))]
+                }
+            }
+            _ => self.parse_block()
+        };
+        let locals = self.locals.pop().unwrap();
+        self.type_params.truncate(self.type_params.len() - type_params.len());
 
         let result = Expr::DecFunc(
             name,
@@ -518,9 +829,58 @@ impl Parser {
             typ
         );
 
+        // An anonymous function inside another function keeps the locals it uses. Variables
+        // declared at the top level are visible to every function already.
+        if is_anonymous {
+            let mut used = vec![];
+            used_names(&result, &mut used);
+
+            let mut captures: Vec<String> = vec![];
+            for name in used {
+                let enclosing = self.locals.iter().any(|frame| frame.contains(&name));
+                if enclosing && !locals.contains(&name) && !captures.contains(&name) {
+                    captures.push(name);
+                }
+            }
+
+            if captures.len() > 0 {
+                return Expr::Closure(Box::new(result), captures);
+            }
+        }
+
         return result;
     }
 
+    /// Parses the type parameters of a generic function or class, such as `<T, U>`, if there are
+    /// any. They can be used as types until the caller removes them from `self.type_params`.
+    fn parse_type_params(&mut self) -> Vec<String> {
+        let mut names = vec![];
+        match self.peek() {
+            Token::LArrow => {
+                self.advance(); // Consume `<`
+                loop {
+                    match self.advance() {
+                        Token::Identifier(name) => names.push(name),
+                        Token::Comma => {},
+                        Token::RArrow => break,
+                        token => panic!("Expected a type parameter, got {:?}", token)
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        self.type_params.extend(names.iter().cloned());
+        names
+    }
+
+    /// Remembers that `name` was declared in the function being parsed, if any.
+    fn declare_local(&mut self, name: &String) {
+        if let Some(frame) = self.locals.last_mut() {
+            frame.push(name.clone());
+        }
+    }
+
     /// Parses a block of code, which is assumed to be a series of statements.
     fn parse_block(&mut self) -> Vec<Expr> {
         let mut block = vec![];
@@ -586,9 +946,41 @@ impl Parser {
                         _ => {}
                     }
 
-                    let arg = Expr::Parameter(param, typ);
+                    let mut default = None;
+                    match self.peek() {
+                        Token::Equal => {
+                            self.advance(); // Consume `=`
+                            default = Some(Box::new(self.parse_expression()));
+                        }
+                        _ => {}
+                    }
+
+                    let arg = Expr::Parameter(param, typ, default);
                     params.push(arg);
                 },
+                Token::Ellipsis => {
+                    self.advance(); // Consume `...`
+                    let name = match self.advance() {
+                        Token::Identifier(name) => name,
+                        token => panic!("Expected a name for the rest parameter, got {:?}", token)
+                    };
+
+                    // Without a type, the rest parameter is an array of whatever is passed.
+                    let mut typ = MType::Nested(Box::new(MType::Array), Box::new(MType::Undefined));
+                    match self.peek() {
+                        Token::Colon => {
+                            typ = self.parse_type()
+                        }
+                        _ => {}
+                    }
+
+                    match self.peek() {
+                        Token::RParen => {}
+                        token => panic!("The rest parameter `...{}` must be the last parameter, got {:?} after it", name, token)
+                    }
+
+                    params.push(Expr::RestParameter(name, typ));
+                },
                 _ => {
                     panic!("Unexpected token while parsing params: {:?}", token);
                 }
@@ -666,9 +1058,32 @@ impl Parser {
         };
 
         self.advance();
-        self.advance();
-        let index = self.parse_expression();
-        self.advance();
+        let line = self.line();
+        let mut result = Expr::Identifier(ident);
+
+        // `grid[0][1]` indexes the result of `grid[0]`.
+        loop {
+            match self.peek() {
+                Token::LBracket => {
+                    self.advance(); // Consume `[`
+                    let index = self.parse_expression();
+                    self.advance(); // Consume `]`
+
+                    result = Expr::ArrayAccess(Box::new(result), Box::new(index), line);
+                }
+                _ => break
+            }
+        }
+
+        // `names[0].length`
+        match self.peek() {
+            Token::Dot => {
+                self.advance(); // Consume `.`
+                let property = self.parse_identifier();
+                return Expr::PropertyAccess(Box::new(result), Box::new(property));
+            }
+            _ => {}
+        }
 
         match self.peek() {
             Token::Semicolon => {
@@ -677,8 +1092,6 @@ impl Parser {
             _ => {}
         }
 
-        let result = Expr::ArrayAccess(ident, Box::new(index));
-
         return result;
     }
 
@@ -686,8 +1099,66 @@ impl Parser {
     fn parse_declaration(&mut self) -> Expr {
         self.advance(); // Consume identifier
 
-        let name = match self.peek() {
-            Token::Identifier(name) => name,
+        let target = match self.peek() {
+            Token::Identifier(name) => Expr::Identifier(name),
+            Token::LParen => { // let (a, b) = ...
+                let mut names = vec![];
+                self.advance(); // Consume `(`
+                loop {
+                    match self.peek() {
+                        Token::Identifier(name) => names.push(Expr::Identifier(name)),
+                        Token::Comma => {},
+                        Token::RParen => break,
+                        _ => panic!("Expected an identifier to destructure into, got {:?}", self.peek())
+                    }
+                    self.advance();
+                }
+                Expr::Tuple(Box::new(names))
+            }
+            Token::LBracket => { // let [a, b] = ...
+                let mut names = vec![];
+                self.advance(); // Consume `[`
+                let line = self.line();
+                loop {
+                    match self.peek() {
+                        Token::Identifier(name) => {
+                            // Each name remembers the index it reads, and the line for bounds check errors.
+                            let index = Expr::Number(names.len() as i64);
+                            names.push(Expr::ArrayAccess(Box::new(Expr::Identifier(name)), Box::new(index), line));
+                        }
+                        Token::Comma => {},
+                        Token::RBracket => break,
+                        _ => panic!("Expected an identifier to destructure into, got {:?}", self.peek())
+                    }
+                    self.advance();
+                }
+                Expr::Array(Box::new(names))
+            }
+            Token::LCurly => { // let { name, age: years } = ...
+                let mut fields = vec![];
+                self.advance(); // Consume `{`
+                loop {
+                    match self.peek() {
+                        Token::Identifier(field) => {
+                            let mut name = field.clone();
+                            if let Some(Token::Colon) = self.tokens.get(self.current + 1) {
+                                self.advance(); // Consume the field
+                                self.advance(); // Consume `:`
+                                name = match self.peek() {
+                                    Token::Identifier(name) => name,
+                                    _ => panic!("Expected an identifier to destructure `{}` into, got {:?}", field, self.peek())
+                                };
+                            }
+                            fields.push((Expr::Identifier(field), Expr::Identifier(name)));
+                        }
+                        Token::Comma => {},
+                        Token::RCurly => break,
+                        _ => panic!("Expected a field name to destructure, got {:?}", self.peek())
+                    }
+                    self.advance();
+                }
+                Expr::Map(Box::new(fields))
+            }
             _ => panic!("Expected an identifier for declaration, got {:?}", self.peek())
         };
 
@@ -705,6 +1176,13 @@ impl Parser {
 
         let mut value = self.parse_statement();
 
+        match self.peek() {
+            Token::As => {
+                value = self.parse_cast(value);
+            }
+            _ => {}
+        }
+
         match self.peek() {
             Token::Plus => {
                 self.advance();
@@ -725,7 +1203,17 @@ impl Parser {
             _ => {}
         }
 
-        let result = Expr::BinOp(Operator::Declare(typ), Box::new(Expr::Identifier(name)), Box::new(value));
+        let mut names = vec![];
+        match &target {
+            Expr::Map(fields) => fields.iter().for_each(|(_, name)| used_names(name, &mut names)),
+            _ => used_names(&target, &mut names)
+        }
+        for name in names.iter() {
+            self.declare_local(name);
+        }
+
+        infer_function_type(&mut value, &typ);
+        let result = Expr::BinOp(Operator::Declare(typ), Box::new(target), Box::new(value));
 
         match self.peek() {
             Token::Semicolon => {
@@ -737,15 +1225,15 @@ impl Parser {
         return result;
     }
 
-    fn parse_assignment(&mut self) -> Expr {
-        self.retreat();
-        self.expressions.pop().unwrap();
-        let assignee = match self.peek() {
-            Token::Identifier(name) => Expr::Identifier(name),
-            _ => panic!("Expected an identifier for assignment, got {:?}", self.peek())
-        };
-        self.advance();
-        self.advance();
+    /// Parses the `= value` of an assignment to `assignee`, which may be a variable, an array
+    /// element or a property.
+    fn parse_assignment(&mut self, assignee: Expr) -> Expr {
+        match assignee {
+            Expr::Identifier(_) | Expr::ArrayAccess(_, _, _) | Expr::PropertyAccess(_, _) => {},
+            _ => panic!("Cannot assign to {:?}", assignee)
+        }
+
+        self.advance(); // Consume `=`
 
         let value = self.parse_expression();
 
@@ -807,9 +1295,18 @@ impl Parser {
             Token::Number(_) => {
                 self.parse_number()
             },
+            Token::Char(_) => {
+                self.parse_char()
+            },
             Token::LBracket => {
                 self.parse_array()
             },
+            Token::LCurly => {
+                self.parse_map()
+            },
+            Token::LParen => {
+                self.parse_parenthesized()
+            },
             Token::RBracket => {
                 self.advance();
                 return Expr::Array(Box::new(vec![]));
@@ -821,6 +1318,13 @@ impl Parser {
             _ => panic!("Expected a number, string, or identifier, got {:?}", self.peek())
         };
 
+        match self.peek() {
+            Token::As => {
+                result = self.parse_cast(result);
+            }
+            _ => {}
+        }
+
         loop {
             let operator = self.peek();
             let operator = match operator {
@@ -853,6 +1357,90 @@ impl Parser {
         return result;
     }
 
+    /// Parses whatever starts with `(`: an anonymous function such as `(a: Number): Number { ... }`,
+    /// a tuple such as `(fd, error)`, or a single value in parentheses.
+    fn parse_parenthesized(&mut self) -> Expr {
+        // Find the matching `)`, a function has its return type or body right after it.
+        let mut depth = 0;
+        let mut i = self.current;
+        while i < self.tokens.len() {
+            match self.tokens[i] {
+                Token::LParen => depth += 1,
+                Token::RParen => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+
+        match self.tokens.get(i + 1) {
+            Some(Token::LCurly) | Some(Token::Colon) | Some(Token::FatArrow) => return self.parse_function(true),
+            _ => {}
+        }
+
+        self.advance(); // Consume `(`
+        let mut items = vec![];
+        let mut trailing_comma = false;
+        loop {
+            match self.peek() {
+                Token::RParen => {
+                    self.advance(); // Consume `)`
+                    break;
+                }
+                Token::Comma => {
+                    self.advance(); // Consume `,`
+                    trailing_comma = true;
+                }
+                _ => {
+                    items.push(self.parse_expression());
+                    trailing_comma = false;
+                }
+            }
+        }
+
+        // `(value)` is just the value, `(value,)` is a tuple of one.
+        if items.len() == 1 && !trailing_comma {
+            return items.pop().unwrap();
+        }
+
+        return Expr::Tuple(Box::new(items));
+    }
+
+    /// Parses a map literal, such as `{ "host": "localhost", "port": "8080" }`.
+    ///
+    /// Blocks are only ever parsed by `parse_block`, so a `{` that reaches this point is a map.
+    fn parse_map(&mut self) -> Expr {
+        self.advance(); // Consume `{`
+        let mut entries = vec![];
+        loop {
+            match self.peek() {
+                Token::RCurly => {
+                    self.advance(); // Consume `}`
+                    break;
+                },
+                Token::Comma => {
+                    self.advance(); // Consume `,`
+                },
+                _ => {
+                    let key = self.parse_expression();
+                    match self.advance() {
+                        Token::Colon => {},
+                        token => panic!("Expected `:` after map key, got {:?}", token)
+                    }
+                    let value = self.parse_expression();
+
+                    entries.push((key, value));
+                }
+            }
+        }
+
+        return Expr::Map(Box::new(entries));
+    }
+
     fn parse_array(&mut self) -> Expr {
         self.advance(); // Consume `[`
         let mut elements = vec![];
@@ -898,17 +1486,109 @@ impl Parser {
         return result;
     }
 
-    /// Parses a `Token::String` into an `Expr::String`.
+    /// Parses a `Token::Char` into an `Expr::Char`.
+    fn parse_char(&mut self) -> Expr {
+        let token = self.advance();
+        let result = match token {
+            Token::Char(value) => Expr::Char(value),
+            _ => panic!("Expected a character, got {:?}", token)
+        };
+
+        return result;
+    }
+
+    /// Parses a `Token::String` into an `Expr::String`, or an `Expr::Interpolation` if it embeds
+    /// any expressions.
     fn parse_string(&mut self) -> Expr {
         let token = self.advance();
         let result = match token {
-            Token::String(value) => Expr::String(value),
+            Token::String(value) => self.parse_string_literal(value),
             _ => panic!("Expected a string, got {:?}", token)
         };
 
         return result;
     }
 
+    /// Splits a string literal into its text and `{expression}` parts.
+    ///
+    /// `{{` and `}}` produce literal braces.
+    ///
+    /// Example:
+    /// ```rs
+    /// "My name is {this.name} and I am {this.age} years old"
+    /// ```
+    fn parse_string_literal(&mut self, value: String) -> Expr {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut interpolated = false;
+
+        let mut chars = value.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    if chars.peek() == Some(&'{') {
+                        chars.next();
+                        literal.push('{');
+                        continue;
+                    }
+
+                    let mut source = String::new();
+                    let mut depth = 1;
+                    loop {
+                        match chars.next() {
+                            Some('{') => {
+                                depth += 1;
+                                source.push('{');
+                            }
+                            Some('}') => {
+                                depth -= 1;
+                                if depth == 0 {
+                                    break;
+                                }
+                                source.push('}');
+                            }
+                            Some(c) => source.push(c),
+                            None => panic!("Unterminated `{{` in string: {:?}", value)
+                        }
+                    }
+
+                    if literal.len() > 0 {
+                        parts.push(Expr::String(literal.clone()));
+                        literal.clear();
+                    }
+
+                    let mut parser = Parser::new(tokenizer::tokenize(source.clone()));
+                    let expr = parser.parse_expression();
+                    if parser.current < parser.tokens.len() {
+                        panic!("Unexpected tokens in string interpolation: {:?}", source);
+                    }
+
+                    parts.push(expr);
+                    interpolated = true;
+                },
+                '}' => {
+                    if chars.peek() == Some(&'}') {
+                        chars.next();
+                    }
+                    literal.push('}');
+                },
+                _ => {
+                    literal.push(c);
+                }
+            }
+        }
+
+        if !interpolated {
+            return Expr::String(literal);
+        }
+
+        if literal.len() > 0 {
+            parts.push(Expr::String(literal));
+        }
+
+        return Expr::Interpolation(Box::new(parts));
+    }
+
     /// Parses a property access.
     ///
     /// Assumes that the object we are accessing from is the last expression in `self.expressions`
@@ -920,7 +1600,14 @@ impl Parser {
         self.advance();
         self.advance();
 
-        let property = self.parse_expression();
+        // Only the property itself, operators after it belong to the enclosing expression.
+        let property = match self.peek() {
+            Token::Number(n) => { // tuple.0
+                self.advance();
+                Expr::Number(n)
+            }
+            _ => self.parse_identifier()
+        };
 
         let result = Expr::PropertyAccess(
             Box::new(object),
@@ -951,6 +1638,14 @@ impl Parser {
 
     }
 
+    /// Returns the line the previous token is on, starting at `1`.
+    fn line(&self) -> usize {
+        match self.lines.get(self.current.saturating_sub(1)) {
+            Some(line) => *line,
+            None => 0
+        }
+    }
+
     /// Returns the current token without advancing.
     fn peek(&self) -> Token {
         match self.tokens.get(self.current) {
@@ -964,3 +1659,99 @@ impl Parser {
     }
 }
 
+
+/// Collects every variable and function name that `expr` refers to, including inside nested
+/// functions.
+fn used_names(expr: &Expr, names: &mut Vec<String>) {
+    let mut visit = |exprs: &Vec<Expr>| {
+        for expr in exprs.iter() {
+            used_names(expr, names);
+        }
+    };
+
+    match expr {
+        Expr::Identifier(name) => {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        Expr::BinOp(_, left, right) => visit(&vec![*left.clone(), *right.clone()]),
+        Expr::Interpolation(items) | Expr::Array(items) | Expr::Tuple(items) => visit(items),
+        Expr::Map(entries) => {
+            for (key, value) in entries.iter() {
+                visit(&vec![key.clone(), value.clone()]);
+            }
+        }
+        Expr::ArrayAccess(target, index, _) => visit(&vec![*target.clone(), *index.clone()]),
+        Expr::Cast(value, _) => visit(&vec![*value.clone()]),
+        // Only the object, properties are not variables.
+        Expr::PropertyAccess(object, property) => {
+            visit(&vec![*object.clone()]);
+            if let Expr::CallFunc(_, args) = &**property {
+                visit(args);
+            }
+        }
+        Expr::If(cond, body, else_body) => {
+            visit(&vec![*cond.clone()]);
+            visit(body);
+            visit(else_body);
+        }
+        Expr::While(cond, body) => {
+            visit(&vec![*cond.clone()]);
+            visit(body);
+        }
+        Expr::For(_, iterable, body, _) => {
+            visit(&vec![*iterable.clone()]);
+            visit(body);
+        }
+        Expr::DecFunc(_, _, body, _) => visit(body),
+        Expr::Closure(func, _) => visit(&vec![*func.clone()]),
+        Expr::CallFunc(callee, args) => {
+            visit(&vec![*callee.clone()]);
+            visit(args);
+        }
+        Expr::NamedArg(_, value) | Expr::Spread(value) => visit(&vec![*value.clone()]),
+        Expr::Return(value) => visit(&vec![*value.clone()]),
+        _ => {}
+    }
+}
+
+/// Gives the anonymous function `func` the parameter and return types of `expected`, where it
+/// has none of its own.
+fn infer_function_type(func: &mut Expr, expected: &MType) {
+    // The type parameters of a generic function are only known once it is called.
+    if expected.is_generic() {
+        return;
+    }
+
+    let (param_types, returns) = match expected {
+        MType::Fn(params, returns) => (params.clone(), returns),
+        MType::Nested(parent, returns) if **parent == MType::Function => (vec![], returns),
+        _ => return
+    };
+
+    match func {
+        Expr::DecFunc(_, params, _, typ) => {
+            // Parameters the type says nothing about, like with `Function<Number>`, are numbers.
+            for (i, param) in params.iter_mut().enumerate() {
+                if let Expr::Parameter(_, typ, _) = param {
+                    if *typ == MType::Undefined {
+                        *typ = param_types.get(i).cloned().unwrap_or(MType::Number);
+                    }
+                }
+            }
+            if *typ == MType::Undefined {
+                *typ = *returns.clone();
+            }
+        }
+        Expr::Closure(func, _) => infer_function_type(func, expected),
+        _ => {}
+    }
+}
+
+/// `path` relative to the working directory when it is in it, the way files are usually named on
+/// the command line.
+fn display_path(path: &Path) -> String {
+    let relative = std::env::current_dir().ok().and_then(|directory| path.strip_prefix(directory).ok().map(Path::to_path_buf));
+    relative.unwrap_or(path.to_path_buf()).display().to_string()
+}
@@ -5,8 +5,18 @@ pub fn tokenize(source: String) -> Vec<Token> {
 
     let lines: Vec<&str> = source.lines().collect();
 
+    // String state is kept across lines, so that `"""` strings can span several of them.
     let mut in_string = false;
-    let mut declaring_for = false;
+    let mut string_raw = false;
+    let mut string_triple = false;
+    let mut skip_newline = false;
+
+    // Lines that ended inside of a string, their `NewLine`s are pushed once the string is closed.
+    let mut pending_lines = 0;
+
+    // `/* */` comments can span several lines too. They are skipped before anything else is
+    // read, so that a `'` or `"` in them does not start a literal.
+    let mut in_block_comment = false;
 
     for line in lines {
         let mut in_comment = false;
@@ -15,31 +25,76 @@ pub fn tokenize(source: String) -> Vec<Token> {
         // This is to prevent `//` comments from triggering even if they are in seperate lines.
         let mut slash_in_line = false; 
 
-        for c in line.chars() {
+        // Used to tell `a b` apart from `ab`: a space ends the identifier before it.
+        let mut previous = ' ';
+
+        let chars: Vec<char> = line.chars().collect();
+        let mut k = 0;
+        while k < chars.len() {
+            let c = chars[k];
+            k += 1;
+
             if in_comment {
                 continue;
             }
 
-            if c != '"' && in_string {
-                let last = tokens.pop().unwrap_or(Token::String(String::new()));
-                
-                match last {
-                    Token::Comma => {
-                        tokens.push(last);
-                        tokens.push(Token::String(c.to_string()));
+            if in_block_comment {
+                if c == '*' && k < chars.len() && chars[k] == '/' {
+                    k += 1;
+                    in_block_comment = false;
+                    previous = ' ';
+                }
+                continue;
+            }
+
+            let before = previous;
+            let after_space = before == ' ';
+            previous = c;
+
+            if in_string {
+                let text = match c {
+                    '\\' if !string_raw => {
+                        if k >= chars.len() {
+                            // A trailing `\` joins the line with the next one.
+                            skip_newline = true;
+                            continue;
+                        }
+                        read_escape(&chars, &mut k, true)
                     },
-                    Token::String(s) => {
-                        tokens.push(Token::String(s + &c.to_string()));
+                    '"' => {
+                        if !string_triple {
+                            in_string = false;
+                            continue;
+                        }
+
+                        if k + 1 < chars.len() && chars[k] == '"' && chars[k + 1] == '"' {
+                            k += 2;
+                            in_string = false;
+                            continue;
+                        }
+
+                        c.to_string()
                     },
-                    _ => {
-                        tokens.push(last);
-                        tokens.push(Token::String(c.to_string()));
-                    }
+                    // Raw strings are never interpolated.
+                    '{' if string_raw => "{{".to_string(),
+                    '}' if string_raw => "}}".to_string(),
+                    _ => c.to_string()
+                };
+
+                match tokens.last_mut() {
+                    Some(Token::String(s)) => s.push_str(&text),
+                    _ => tokens.push(Token::String(text))
                 }
 
                 continue;
             }
 
+            if c == '/' && k < chars.len() && chars[k] == '*' {
+                k += 1;
+                in_block_comment = true;
+                continue;
+            }
+
             match c {
                 '0'..='9' => {
                     // If the last token is a number, append the digit to it.
@@ -155,11 +210,62 @@ pub fn tokenize(source: String) -> Vec<Token> {
                 '(' => tokens.push(Token::LParen),
                 ')' => tokens.push(Token::RParen),
                 '"' => {
-                    if !in_string{
-                        tokens.push(Token::String(String::new()));
+                    // `r"..."` is a raw string, where backslashes and braces are taken literally.
+                    string_raw = false;
+                    if before == 'r' {
+                        match tokens.last() {
+                            Some(Token::Identifier(name)) if name == "r" => {
+                                tokens.pop();
+                                string_raw = true;
+                            },
+                            _ => {}
+                        }
+                    }
+
+                    // `"""` starts a string that may span several lines.
+                    string_triple = k + 1 < chars.len() && chars[k] == '"' && chars[k + 1] == '"';
+                    if string_triple {
+                        k += 2;
+                        skip_newline = k >= chars.len(); // Ignore the newline right after `"""`
                     }
 
-                    in_string = !in_string
+                    tokens.push(Token::String(String::new()));
+                    in_string = true;
+                },
+                '\'' => {
+                    if k >= chars.len() {
+                        panic!("Unterminated character literal: {}", line);
+                    }
+
+                    let text = match chars[k] {
+                        '\\' => {
+                            k += 1;
+                            read_escape(&chars, &mut k, false)
+                        },
+                        c => {
+                            k += 1;
+                            c.to_string()
+                        }
+                    };
+
+                    let value = match text.as_str() {
+                        "{{" => '{',
+                        "}}" => '}',
+                        _ => text.chars().next().unwrap()
+                    };
+
+                    if k >= chars.len() || chars[k] != '\'' {
+                        panic!("Expected `'` to close character literal: {}", line);
+                    }
+                    k += 1;
+
+                    // Strings are bytes, so characters are too.
+                    if value as u32 > 255 {
+                        panic!("Character literal {:?} does not fit in a single byte", value);
+                    }
+
+                    previous = '\'';
+                    tokens.push(Token::Char(value));
                 },
                 ';' => {
                     let last = tokens.pop().unwrap();
@@ -181,19 +287,37 @@ pub fn tokenize(source: String) -> Vec<Token> {
                         }
                     }
                 },
-                '{' => {
-                    tokens.push(Token::LCurly);
-                    if declaring_for {
-                        declaring_for = false;
+                '{' => tokens.push(Token::LCurly),
+                '}' => tokens.push(Token::RCurly),
+                '>' => {
+                    match tokens.last() {
+                        Some(Token::Equal) => {
+                            tokens.pop();
+                            tokens.push(Token::FatArrow);
+                        },
+                        Some(Token::Minus) => {
+                            tokens.pop();
+                            tokens.push(Token::ThinArrow);
+                        },
+                        _ => tokens.push(Token::RArrow)
                     }
                 },
-                '}' => tokens.push(Token::RCurly),
-                '>' => tokens.push(Token::RArrow),
                 '<' => tokens.push(Token::LArrow),
                 '[' => tokens.push(Token::LBracket),
                 ']' => tokens.push(Token::RBracket),
                 ',' => tokens.push(Token::Comma),
-                '.' => tokens.push(Token::Dot),
+                '.' => {
+                    // `...` is a single token, for rest parameters and spread arguments.
+                    let len = tokens.len();
+                    match (len >= 2, tokens.last()) {
+                        (true, Some(Token::Dot)) if matches!(tokens[len - 2], Token::Dot) => {
+                            tokens.pop();
+                            tokens.pop();
+                            tokens.push(Token::Ellipsis);
+                        },
+                        _ => tokens.push(Token::Dot)
+                    }
+                },
                 ':' => tokens.push(Token::Colon),
                 '!' => tokens.push(Token::Not),
                 ' ' => {
@@ -218,7 +342,6 @@ pub fn tokenize(source: String) -> Vec<Token> {
                             }
                             else if s == "for" {
                                 tokens.push(Token::For);
-                                declaring_for = true;
                             }
                             else if s == "of" {
                                 tokens.push(Token::Of);
@@ -232,6 +355,9 @@ pub fn tokenize(source: String) -> Vec<Token> {
                             else if s == "include" {
                                 tokens.push(Token::Include);
                             }
+                            else if s == "export" {
+                                tokens.push(Token::Export);
+                            }
                             else if s == "class" {
                                 tokens.push(Token::Class);
                             }
@@ -241,12 +367,11 @@ pub fn tokenize(source: String) -> Vec<Token> {
                             else if s == "pass" {
                                 tokens.push(Token::Pass);
                             }
+                            else if s == "as" {
+                                tokens.push(Token::As);
+                            }
                             else {
                                 tokens.push(Token::Identifier(s.clone()));
-                                if declaring_for {
-                                    tokens.push(Token::Identifier("".to_string()));
-                                    declaring_for = false;
-                                }
                             }
                         },
                         _ => {
@@ -255,140 +380,135 @@ pub fn tokenize(source: String) -> Vec<Token> {
                     }
                 },
                 _ => {
-                    if in_string {
-                        let last = tokens.pop().unwrap_or(Token::String(String::new()));
-
-                        match last {
-                            Token::String(s) => {
-                                tokens.push(Token::String(s + &c.to_string()));
-                            },
-                            _ => {
-                                tokens.push(last);
-                                tokens.push(Token::String(c.to_string()));
-                            }
-                        }
+                    if tokens.len() == 0 {
+                        tokens.push(Token::Identifier(c.to_string()));
+                        continue;
                     }
-                    else {
-                        if tokens.len() == 0 {
-                            tokens.push(Token::Identifier(c.to_string()));
-                            continue;
-                        }
 
-                        let last = tokens.pop().unwrap();
-                        match last {
-                            Token::Identifier(name) => {
-                                if !c.is_alphabetic() {
-                                    panic!("Identifier must be alphanumeric: {name}{c}");
-                                }
-
-                                tokens.push(Token::Identifier(name + &c.to_string()));
-                            },
-                            Token::Equal => {
-                                tokens.push(last);
-                                tokens.push(Token::Identifier(c.to_string()));
-                            },
-                            Token::Semicolon => {
-                                tokens.push(last);
-                                tokens.push(Token::Identifier(c.to_string()));
-                            },
-                            _ => {
-                                tokens.push(last);
-                                tokens.push(Token::Identifier(c.to_string()));
+                    let last = tokens.pop().unwrap();
+                    match last {
+                        Token::Identifier(name) if after_space => {
+                            tokens.push(Token::Identifier(name));
+                            tokens.push(Token::Identifier(c.to_string()));
+                        },
+                        Token::Identifier(name) => {
+                            if !c.is_alphabetic() {
+                                panic!("Identifier must be alphanumeric: {name}{c}");
                             }
+
+                            tokens.push(Token::Identifier(name + &c.to_string()));
+                        },
+                        Token::Equal => {
+                            tokens.push(last);
+                            tokens.push(Token::Identifier(c.to_string()));
+                        },
+                        Token::Semicolon => {
+                            tokens.push(last);
+                            tokens.push(Token::Identifier(c.to_string()));
+                        },
+                        _ => {
+                            tokens.push(last);
+                            tokens.push(Token::Identifier(c.to_string()));
                         }
                     }
                 }
             }
         }
 
-        // tokens.push(Token::NewLine);
-    }
-
-    // Format strings (newlines, tabs, etc.)
-    let mut new_tokens: Vec<Token> = Vec::new();
-    let mut in_string = false;
-    let mut in_escape = false;
-    for token in tokens.clone() {
-        match token {
-            Token::String(s) => {
-                let mut new_string = String::new();
-                for c in s.chars() {
-                    if in_escape {
-                        match c {
-                            'n' => new_string.push('\n'),
-                            't' => new_string.push('\t'),
-                            _ => new_string.push(c)
-                        }
+        if in_string {
+            if !string_triple {
+                panic!("Unterminated string literal, use `\"\"\"` for strings that span several lines: {}", line);
+            }
 
-                        in_escape = false;
-                    }
-                    else {
-                        match c {
-                            '\\' => in_escape = true,
-                            _ => new_string.push(c)
-                        }
-                    }
+            if skip_newline {
+                skip_newline = false;
+            } else {
+                match tokens.last_mut() {
+                    Some(Token::String(s)) => s.push('\n'),
+                    _ => {}
                 }
+            }
+        }
 
-                new_tokens.push(Token::String(new_string));
-            },
-            _ => {
-                new_tokens.push(token);
+        // The parser uses these to tell which line a token is on.
+        if in_string {
+            pending_lines += 1;
+        } else {
+            for _ in 0..pending_lines + 1 {
+                tokens.push(Token::NewLine);
             }
+            pending_lines = 0;
         }
     }
 
-    tokens = new_tokens;
-
-    // Remove multiline comments
-    let mut new_tokens: Vec<Token> = Vec::new();
+    if in_string {
+        panic!("Unterminated string literal at the end of the file");
+    }
 
-    let mut in_multiline_comment = false;
-    let mut i = 0;
-    loop {
-        if i >= tokens.len() {
-            break;
-        }
+    return tokens;
+}
 
-        match tokens[i] {
-            Token::Slash => {
-                if i + 1 < tokens.len() {
-                    match tokens[i + 1] {
-                        Token::Star => {
-                            in_multiline_comment = true;
-                        },
-                        _ => {
-                            if !in_multiline_comment {
-                                new_tokens.push(tokens[i].clone());
-                            }
-                        }
-                    }
-                }
-            },
-            Token::Star => {
-                if i + 1 < tokens.len() {
-                    match tokens[i + 1] {
-                        Token::Slash => {
-                            in_multiline_comment = false;
-                            i += 1;
-                        },
-                        _ => {
-                            if !in_multiline_comment {
-                                new_tokens.push(tokens[i].clone());
-                            }
-                        }
-                    }
+/// Reads the escape sequence after a `\` in a string, starting at `chars[*k]`, and returns the text
+/// it stands for.
+///
+/// Braces, from `\{` and `\}` or from a code like `\x7B`, are returned as `{{` and `}}`, so that
+/// the parser does not treat them as the start or end of an interpolation. Strings are UTF-8,
+/// so in them `\xNN` only goes up to `\x7F`, which is a single byte.
+fn read_escape(chars: &Vec<char>, k: &mut usize, in_string: bool) -> String {
+    let c = chars[*k];
+    *k += 1;
+
+    let text = match c {
+        'n' => "\n".to_string(),
+        't' => "\t".to_string(),
+        'r' => "\r".to_string(),
+        '0' => "\0".to_string(),
+        '\\' => "\\".to_string(),
+        '"' => "\"".to_string(),
+        '\'' => "'".to_string(),
+        '{' => "{".to_string(),
+        '}' => "}".to_string(),
+        'x' => { // \x41
+            let digits: String = chars.iter().skip(*k).take(2).collect();
+            *k += digits.len();
+
+            match u8::from_str_radix(&digits, 16) {
+                Ok(byte) if byte > 0x7F && in_string => {
+                    panic!("\\x{} is not a single byte in a string, use \\u{{{:X}}} for the character", digits, byte)
                 }
+                Ok(byte) if digits.len() == 2 => (byte as char).to_string(),
+                _ => panic!("Invalid escape sequence: \\x{}", digits)
             }
-            _ => {
-                if !in_multiline_comment {
-                    new_tokens.push(tokens[i].clone());
-                }
+        },
+        'u' => { // \u{1F308}
+            if *k >= chars.len() || chars[*k] != '{' {
+                panic!("Expected `{{` after \\u in escape sequence");
             }
-        }
+            *k += 1;
 
-        i +=1 ;
-    }
+            let mut digits = String::new();
+            while *k < chars.len() && chars[*k] != '}' {
+                digits.push(chars[*k]);
+                *k += 1;
+            }
+            *k += 1; // Consume `}`
 
-    return new_tokens;
+            let code = match u32::from_str_radix(&digits, 16) {
+                Ok(code) => code,
+                Err(_) => panic!("Invalid escape sequence: \\u{{{}}}", digits)
+            };
+
+            match char::from_u32(code) {
+                Some(c) => c.to_string(),
+                None => panic!("Invalid unicode code point in escape sequence: \\u{{{}}}", digits)
+            }
+        },
+        _ => panic!("Unknown escape sequence: \\{}", c)
+    };
+
+    match text.as_str() {
+        "{" => "{{".to_string(),
+        "}" => "}}".to_string(),
+        _ => text
+    }
 }